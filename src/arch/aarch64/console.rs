@@ -41,6 +41,21 @@ impl Console {
 		}
 	}
 
+	/// Receives a byte from the PL011 UART if one is available.
+	pub fn read_byte(&mut self) -> Option<u8> {
+		/// Offset of the flag register.
+		const UARTFR: usize = 0x18;
+		/// Receive FIFO empty.
+		const RXFE: u8 = 1 << 4;
+
+		let flags = unsafe { self.stdout.as_ptr().add(UARTFR).read_volatile() };
+		if flags & RXFE != 0 {
+			return None;
+		}
+
+		Some(unsafe { self.stdout.as_ptr().read_volatile() })
+	}
+
 	pub(super) fn get_stdout(&self) -> NonNull<u8> {
 		self.stdout
 	}
@@ -194,11 +194,3 @@ unsafe fn pre_init() -> ! {
 
 	unreachable!()
 }
-
-pub unsafe fn wait_forever() -> ! {
-	loop {
-		unsafe {
-			asm!("wfe");
-		}
-	}
-}
@@ -19,6 +19,8 @@ _start:
 	adrp	x4, __boot_core_stack_end_exclusive
 	add		x4, x4, #:lo12:__boot_core_stack_end_exclusive
 	mov		sp, x4
+	// Terminate the frame pointer chain for backtraces.
+	mov		x29, xzr
 	
 	// Jump to Rust code.
 	b	_start_rust
@@ -0,0 +1,151 @@
+//! CPU exception handlers that report the exception and stop the machine.
+
+use core::arch::global_asm;
+use core::{fmt, ptr};
+
+use aarch64_cpu::asm::barrier;
+use aarch64_cpu::registers::{Writeable, VBAR_EL1};
+
+use crate::backtrace::Backtrace;
+
+// Each vector saves x0 and x1 and passes its index to the common handler in x0.
+global_asm!(
+	".section .text",
+	".balign 0x800",
+	"exception_vectors:",
+	".irp index, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15",
+	".balign 0x80",
+	"sub sp, sp, #{frame_size}",
+	"stp x0, x1, [sp, #0]",
+	"mov x0, #\\index",
+	"b exception_common",
+	".endr",
+	"exception_common:",
+	"stp x2, x3, [sp, #16]",
+	"stp x4, x5, [sp, #32]",
+	"stp x6, x7, [sp, #48]",
+	"stp x8, x9, [sp, #64]",
+	"stp x10, x11, [sp, #80]",
+	"stp x12, x13, [sp, #96]",
+	"stp x14, x15, [sp, #112]",
+	"stp x16, x17, [sp, #128]",
+	"stp x18, x19, [sp, #144]",
+	"stp x20, x21, [sp, #160]",
+	"stp x22, x23, [sp, #176]",
+	"stp x24, x25, [sp, #192]",
+	"stp x26, x27, [sp, #208]",
+	"stp x28, x29, [sp, #224]",
+	"stp x30, x0, [sp, #240]",
+	"mrs x1, elr_el1",
+	"mrs x2, spsr_el1",
+	"stp x1, x2, [sp, #256]",
+	"mrs x1, esr_el1",
+	"mrs x2, far_el1",
+	"stp x1, x2, [sp, #272]",
+	"mov x0, sp",
+	"bl {handle_exception}",
+	"brk #0",
+	frame_size = const core::mem::size_of::<ExceptionFrame>(),
+	handle_exception = sym handle_exception,
+);
+
+extern "C" {
+	static exception_vectors: u8;
+}
+
+/// The stack of the exception vectors.
+#[repr(C)]
+struct ExceptionFrame {
+	x: [u64; 31],
+	/// The index of the vector in the vector table.
+	index: u64,
+	elr: u64,
+	spsr: u64,
+	esr: u64,
+	far: u64,
+}
+
+impl ExceptionFrame {
+	fn kind(&self) -> &'static str {
+		match self.index % 4 {
+			0 => "Synchronous exception",
+			1 => "IRQ",
+			2 => "FIQ",
+			_ => "SError",
+		}
+	}
+
+	fn source(&self) -> &'static str {
+		match self.index / 4 {
+			0 => "current EL with SP_EL0",
+			1 => "current EL with SP_ELx",
+			2 => "lower EL using AArch64",
+			_ => "lower EL using AArch32",
+		}
+	}
+
+	/// Returns the description of the exception class in ESR_EL1.
+	fn class(&self) -> &'static str {
+		match (self.esr >> 26) & 0x3F {
+			0x00 => "unknown reason",
+			0x01 => "trapped WFI or WFE",
+			0x0E => "illegal execution state",
+			0x15 => "SVC instruction",
+			0x16 => "HVC instruction",
+			0x17 => "SMC instruction",
+			0x18 => "trapped system register access",
+			0x20 | 0x21 => "instruction abort",
+			0x22 => "PC alignment fault",
+			0x24 | 0x25 => "data abort",
+			0x26 => "SP alignment fault",
+			0x2C => "floating-point exception",
+			0x2F => "SError",
+			0x3C => "BRK instruction",
+			_ => "other exception class",
+		}
+	}
+
+	fn is_synchronous(&self) -> bool {
+		self.index.is_multiple_of(4)
+	}
+
+	/// Returns whether FAR_EL1 holds the faulting address.
+	fn is_abort(&self) -> bool {
+		matches!((self.esr >> 26) & 0x3F, 0x20 | 0x21 | 0x22 | 0x24 | 0x25)
+	}
+}
+
+impl fmt::Display for ExceptionFrame {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} from {}", self.kind(), self.source())?;
+		if self.is_synchronous() {
+			write!(f, ": {} (ESR_EL1 = {:#x})", self.class(), self.esr)?;
+		}
+		write!(f, " at {:#x}", self.elr)?;
+		if self.is_synchronous() && self.is_abort() {
+			write!(f, " accessing {:#x}", self.far)?;
+		}
+
+		let sp = ptr::from_ref(self).addr() as u64 + core::mem::size_of::<Self>() as u64;
+		for (i, line) in self.x.chunks(4).enumerate() {
+			writeln!(f)?;
+			for (j, value) in line.iter().enumerate() {
+				let name = i * 4 + j;
+				write!(f, " x{name:<2}={value:#018x}")?;
+			}
+		}
+		write!(f, "  sp={sp:#018x}")?;
+		write!(f, "\n pc ={:#018x} spsr={:#010x}", self.elr, self.spsr)
+	}
+}
+
+extern "C" fn handle_exception(frame: &ExceptionFrame) -> ! {
+	let backtrace = Backtrace::interrupted(frame.elr, frame.x[29]);
+	crate::fatal::report(format_args!("{frame}\n{backtrace}"))
+}
+
+/// Installs the exception vectors in VBAR_EL1.
+pub fn install_exception_handlers() {
+	VBAR_EL1.set(ptr::addr_of!(exception_vectors).addr() as u64);
+	barrier::isb(barrier::SY);
+}
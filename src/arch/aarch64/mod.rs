@@ -1,11 +1,18 @@
 mod console;
 pub use self::console::Console;
 pub mod entry;
+mod exception;
+pub use self::exception::install_exception_handlers;
 pub mod paging;
-
+mod shutdown;
+use alloc::format;
+use alloc::vec::Vec;
 use core::arch::asm;
+use core::convert::Infallible;
+use core::ops::Range;
 use core::ptr::{self, NonNull};
 
+use aarch64_cpu::registers::{Readable, CNTFRQ_EL0, CNTVCT_EL0};
 use align_address::Align;
 use goblin::elf::header::header64::{Header, EI_DATA, ELFDATA2LSB, ELFMAG, SELFMAG};
 use hermit_dtb::Dtb;
@@ -15,11 +22,14 @@ use hermit_entry::Entry;
 use log::info;
 use sptr::Strict;
 
+pub use self::shutdown::{debug_exit, halt, poweroff, reboot};
 use crate::arch::paging::*;
+use crate::error::LoaderError;
 use crate::os::CONSOLE;
-use crate::BootInfoExt;
+use crate::{cmdline, BootInfoExt};
 
 extern "C" {
+	static loader_start: u8;
 	static mut loader_end: u8;
 	static mut l0_pgtable: u64;
 	static mut l1_pgtable: u64;
@@ -37,6 +47,10 @@ const KERNEL_STACK_SIZE: usize = 32_768;
 /// start of RAM (0x4000_0000)
 /// see <https://qemu.readthedocs.io/en/latest/system/arm/virt.html>
 const DEVICE_TREE: u64 = RAM_START;
+/// Size of the RAM that is mapped for the kernel in [`boot_kernel`]
+const MAPPED_RAM_SIZE: u64 = 10 * 512 * BasePageSize::SIZE as u64;
+/// The number of attempts to read `RNDR`
+const RNDR_RETRIES: usize = 10;
 
 #[allow(dead_code)]
 const PT_DEVICE: u64 = 0x707;
@@ -45,37 +59,175 @@ const PT_MEM: u64 = 0x713;
 const PT_MEM_CD: u64 = 0x70F;
 const PT_SELF: u64 = 1 << 55;
 
+/// Returns the current value of the virtual counter.
+pub fn timestamp() -> u64 {
+	CNTVCT_EL0.get()
+}
+
+/// Returns the frequency of the virtual counter in Hz.
+pub fn timestamp_frequency() -> Option<u64> {
+	let frequency = CNTFRQ_EL0.get();
+	(frequency != 0).then_some(frequency)
+}
+
 pub unsafe fn get_memory(_memory_size: u64) -> u64 {
 	(ptr::addr_of_mut!(loader_end).expose_addr() as u64).align_up(LargePageSize::SIZE as u64)
 }
 
-pub fn find_kernel() -> &'static [u8] {
-	let dtb = unsafe {
-		Dtb::from_raw(sptr::from_exposed_addr(DEVICE_TREE as usize))
-			.expect(".dtb file has invalid header")
+/// Returns a random address for a relocatable kernel in the RAM that is mapped for the kernel.
+pub unsafe fn get_random_memory(memory_size: u64) -> Option<u64> {
+	// The kernel stack is placed below the kernel.
+	let start = ptr::addr_of_mut!(loader_end).expose_addr() as u64 + KERNEL_STACK_SIZE as u64;
+	let end = RAM_START + MAPPED_RAM_SIZE;
+
+	let kernel = find_kernel().ok()?.as_ptr_range();
+	let kernel = kernel.start.addr() as u64..kernel.end.addr() as u64;
+	let reserved_memory = reserved_memory();
+
+	crate::kaslr::choose(
+		|| core::iter::once(start..end),
+		|| core::iter::once(kernel.clone()).chain(reserved_memory.iter().cloned()),
+		memory_size,
+		crate::entropy::kaslr_seed,
+	)
+}
+
+/// Returns the memory regions of the children of `/reserved-memory`.
+fn reserved_memory() -> Vec<Range<u64>> {
+	let Ok(dtb) = dtb() else {
+		return Vec::new();
+	};
+
+	// These are the defaults from the specification.
+	let cells = |name, default| {
+		dtb.get_property("/reserved-memory", name)
+			.and_then(|cells| cells.try_into().ok())
+			.map_or(default, u32::from_be_bytes) as usize
+	};
+	let address_cells = cells("#address-cells", 2);
+	let size_cells = cells("#size-cells", 1);
+	if address_cells == 0 || size_cells == 0 {
+		return Vec::new();
+	}
+
+	let read = |cells: &[u8]| {
+		cells.chunks_exact(4).fold(0, |value, cell| {
+			value << 32 | u64::from(u32::from_be_bytes(cell.try_into().unwrap()))
+		})
 	};
 
+	let mut regions = Vec::new();
+	for node in dtb.enum_subnodes("/reserved-memory") {
+		let path = format!("/reserved-memory/{node}");
+		let reg = dtb.get_property(&path, "reg").unwrap_or_default();
+		for region in reg.chunks_exact((address_cells + size_cells) * 4) {
+			let (address, size) = region.split_at(address_cells * 4);
+			let address = read(address);
+			regions.push(address..address + read(size));
+		}
+	}
+	regions
+}
+
+/// Returns a random seed from `/chosen/kaslr-seed` or `/chosen/rng-seed`.
+pub fn firmware_seed() -> Option<u64> {
+	let dtb = dtb().ok()?;
+	crate::kaslr::fdt_seed(
+		dtb.get_property("/chosen", "kaslr-seed"),
+		dtb.get_property("/chosen", "rng-seed"),
+	)
+}
+
+/// Returns a random number from `RNDR` if the processor implements `FEAT_RNG`.
+pub fn random_u64() -> Option<u64> {
+	let isar0: u64;
+	unsafe {
+		asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0, options(nomem, nostack));
+	}
+	// ID_AA64ISAR0_EL1.RNDR[63:60]
+	if isar0 >> 60 == 0 {
+		return None;
+	}
+
+	(0..RNDR_RETRIES).find_map(|_| {
+		let value: u64;
+		let success: u64;
+		unsafe {
+			// RNDR sets NZCV to 0b0100 on failure.
+			asm!(
+				"mrs {value}, s3_3_c2_c4_0",
+				"cset {success}, ne",
+				value = out(reg) value,
+				success = out(reg) success,
+				options(nomem, nostack),
+			);
+		}
+		(success != 0).then_some(value)
+	})
+}
+
+const PROTOCOL: &str = "devicetree";
+
+/// Returns the devicetree from the firmware.
+fn dtb() -> Result<Dtb<'static>, LoaderError> {
+	unsafe { Dtb::from_raw(sptr::from_exposed_addr(DEVICE_TREE as usize)) }.ok_or(
+		LoaderError::BootProtocol {
+			protocol: PROTOCOL,
+			addr: Some(DEVICE_TREE),
+			expected: "a valid devicetree header",
+		},
+	)
+}
+
+/// Returns the devicetree for modifying it in place.
+///
+/// The devicetree may grow until the start of the loader.
+unsafe fn dtb_mut() -> &'static mut [u8] {
+	let len = ptr::addr_of!(loader_start).addr() - DEVICE_TREE as usize;
+	unsafe {
+		core::slice::from_raw_parts_mut(sptr::from_exposed_addr_mut(DEVICE_TREE as usize), len)
+	}
+}
+
+/// Returns the command line from `/chosen/bootargs`.
+pub fn command_line() -> Option<&'static str> {
+	let dtb = dtb().ok()?;
+	let bootargs = dtb.get_property("/chosen", "bootargs")?;
+	let bootargs = core::str::from_utf8(bootargs).ok()?.trim_end_matches('\0');
+	Some(bootargs)
+}
+
+pub fn find_kernel() -> Result<&'static [u8], LoaderError> {
+	let dtb = dtb()?;
+
 	let module_start = dtb
 		.enum_subnodes("/chosen")
-		.find(|node| node.starts_with("module@"))
-		.map(|node| {
-			let value = node.strip_prefix("module@").unwrap();
+		.find_map(|node| node.strip_prefix("module@"))
+		.and_then(|value| {
 			if let Some(value) = value.strip_prefix("0x") {
-				usize::from_str_radix(value, 16).unwrap()
+				usize::from_str_radix(value, 16).ok()
 			} else if let Some(value) = value.strip_prefix("0X") {
-				usize::from_str_radix(value, 16).unwrap()
+				usize::from_str_radix(value, 16).ok()
 			} else {
-				value.parse().unwrap()
+				value.parse().ok()
 			}
 		})
-		.unwrap();
+		.ok_or(LoaderError::BootProtocol {
+			protocol: PROTOCOL,
+			addr: Some(DEVICE_TREE),
+			expected: "the kernel in /chosen/module@<addr>",
+		})?;
 
 	let header = unsafe {
 		&*core::mem::transmute::<*const u8, *const Header>(sptr::from_exposed_addr(module_start))
 	};
 
 	if header.e_ident[0..SELFMAG] != ELFMAG[..] {
-		panic!("Don't found valid ELF file!");
+		return Err(LoaderError::BootProtocol {
+			protocol: PROTOCOL,
+			addr: Some(module_start as u64),
+			expected: "an ELF file in /chosen/module@<addr>",
+		});
 	}
 
 	#[cfg(target_endian = "little")]
@@ -93,24 +245,39 @@ pub fn find_kernel() -> &'static [u8] {
 
 	info!("Found ELF file with size {}", file_size);
 
-	unsafe {
-		core::slice::from_raw_parts(
-			sptr::from_exposed_addr(module_start),
-			file_size.try_into().unwrap(),
-		)
-	}
+	// The module does not tell us its size, so we look for a signature behind the ELF file.
+	let file_size = usize::try_from(file_size).unwrap();
+	let signed_size = file_size + crate::signature::TRAILER_SIZE;
+	let signed =
+		unsafe { core::slice::from_raw_parts(sptr::from_exposed_addr(module_start), signed_size) };
+	let size = if crate::signature::has_trailer(signed) {
+		signed_size
+	} else {
+		file_size
+	};
+
+	Ok(&signed[..size])
 }
 
-pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
+/// Returns the detached signature of the kernel.
+///
+/// Only signatures that are appended to the kernel are supported.
+pub fn detached_signature() -> Option<&'static [u8]> {
+	None
+}
+
+pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> Result<Infallible, LoaderError> {
 	let LoadedKernel {
 		load_info,
 		entry_point,
 	} = kernel_info;
 
-	let dtb = unsafe {
-		Dtb::from_raw(sptr::from_exposed_addr(DEVICE_TREE as usize))
-			.expect(".dtb file has invalid header")
-	};
+	{
+		let env = crate::fdt_patch::env(cmdline::edited().or_else(command_line));
+		crate::fdt_patch::patch(unsafe { dtb_mut() }, &env);
+	}
+
+	let dtb = dtb()?;
 	let cpus = dtb
 		.enum_subnodes("/cpus")
 		.filter(|c| c.split('@').next().unwrap() == "cpu")
@@ -193,19 +360,26 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 		);
 	}
 
-	let dtb = unsafe {
-		Dtb::from_raw(sptr::from_exposed_addr(DEVICE_TREE as usize))
-			.expect(".dtb file has invalid header")
+	let no_memory = LoaderError::Memory {
+		protocol: PROTOCOL,
+		expected: "/memory with device_type = \"memory\" and reg = <u64 u64>",
 	};
 
 	if let Some(device_type) = dtb.get_property("/memory", "device_type") {
 		let device_type = core::str::from_utf8(device_type)
-			.unwrap()
+			.unwrap_or_default()
 			.trim_matches(char::from(0));
-		assert!(device_type == "memory");
+		if device_type != "memory" {
+			return Err(no_memory);
+		}
 	}
 
-	let reg = dtb.get_property("/memory", "reg").unwrap();
+	let Some(reg) = dtb
+		.get_property("/memory", "reg")
+		.filter(|reg| reg.len() == 2 * core::mem::size_of::<u64>())
+	else {
+		return Err(no_memory);
+	};
 	let (start_slice, size_slice) = reg.split_at(core::mem::size_of::<u64>());
 	let ram_start = u64::from_be_bytes(start_slice.try_into().unwrap());
 	let ram_size = u64::from_be_bytes(size_slice.try_into().unwrap());
@@ -224,6 +398,7 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 	let stack = sptr::from_exposed_addr_mut(stack);
 	let entry = sptr::from_exposed_addr(entry_point.try_into().unwrap());
 	let raw_boot_info = boot_info.write();
+	crate::boot_time::finish(Some(unsafe { dtb_mut() }));
 
 	unsafe { enter_kernel(stack, entry, raw_boot_info) }
 }
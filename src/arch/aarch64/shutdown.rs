@@ -0,0 +1,95 @@
+//! Stopping the machine after fatal errors.
+
+use core::arch::{asm, global_asm};
+use core::ptr;
+
+use aarch64_cpu::asm::barrier;
+use aarch64_cpu::registers::{Writeable, VBAR_EL1};
+
+/// See [Arm Power State Coordination Interface](https://developer.arm.com/documentation/den0022/latest/).
+const PSCI_SYSTEM_OFF: u32 = 0x8400_0008;
+const PSCI_SYSTEM_RESET: u32 = 0x8400_0009;
+
+/// See [Semihosting for AArch32 and AArch64](https://github.com/ARM-software/abi-aa/blob/main/semihosting/semihosting.rst).
+const SYS_EXIT: u32 = 0x18;
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+// Vectors that turn off the machine with PSCI if the semihosting call traps.
+global_asm!(
+	".section .text",
+	".balign 0x800",
+	"shutdown_vectors:",
+	".rept 16",
+	".balign 0x80",
+	"b {system_off}",
+	".endr",
+	system_off = sym system_off,
+);
+
+extern "C" {
+	static shutdown_vectors: u8;
+}
+
+pub fn halt() -> ! {
+	loop {
+		unsafe {
+			asm!("wfe", options(nomem, nostack));
+		}
+	}
+}
+
+pub fn reboot() -> ! {
+	psci(PSCI_SYSTEM_RESET);
+	halt()
+}
+
+/// Turns off the machine with a failure status.
+///
+/// This exits QEMU with status `1` if it was started with `-semihosting`.
+/// Otherwise, the semihosting call traps and we fall back to PSCI `SYSTEM_OFF`.
+pub fn poweroff() -> ! {
+	VBAR_EL1.set(ptr::addr_of!(shutdown_vectors).addr() as u64);
+	barrier::isb(barrier::SY);
+
+	let block = [ADP_STOPPED_APPLICATION_EXIT, 1];
+	unsafe {
+		asm!(
+			"hlt #0xf000",
+			inout("w0") SYS_EXIT => _,
+			in("x1") block.as_ptr(),
+			options(nostack),
+		);
+	}
+
+	system_off()
+}
+
+/// Exits QEMU with a failure status, which [`poweroff`] already does using semihosting.
+pub fn debug_exit() -> ! {
+	poweroff()
+}
+
+extern "C" fn system_off() -> ! {
+	psci(PSCI_SYSTEM_OFF);
+	halt()
+}
+
+/// Calls the PSCI `function` using the conduit from `/psci/method`.
+fn psci(function: u32) {
+	let Some(dtb) = super::dtb().ok() else {
+		return;
+	};
+	let Some(method) = dtb.get_property("/psci", "method") else {
+		return;
+	};
+
+	match method.strip_suffix(b"\0").unwrap_or(method) {
+		b"hvc" => unsafe {
+			asm!("hvc #0", inout("w0") function => _, options(nostack), clobber_abi("C"));
+		},
+		b"smc" => unsafe {
+			asm!("smc #0", inout("w0") function => _, options(nostack), clobber_abi("C"));
+		},
+		_ => {}
+	}
+}
@@ -1,3 +1,5 @@
+use core::ptr;
+
 use sbi_rt::Physical;
 use sptr::Strict;
 
@@ -8,4 +10,11 @@ impl Console {
 	pub fn write_bytes(&mut self, bytes: &[u8]) {
 		sbi_rt::console_write(Physical::new(bytes.len(), bytes.as_ptr().expose_addr(), 0));
 	}
+
+	/// Receives a byte from the SBI debug console if one is available.
+	pub fn read_byte(&mut self) -> Option<u8> {
+		let mut byte = 0u8;
+		let ret = sbi_rt::console_read(Physical::new(1, ptr::addr_of_mut!(byte).expose_addr(), 0));
+		(ret.is_ok() && ret.value == 1).then_some(byte)
+	}
 }
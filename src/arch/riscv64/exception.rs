@@ -0,0 +1,150 @@
+//! CPU exception handlers that report the exception and stop the machine.
+
+use core::arch::{asm, global_asm};
+use core::{fmt, ptr};
+
+use crate::backtrace::Backtrace;
+
+global_asm!(
+	".section .text",
+	".balign 4",
+	"exception_entry:",
+	"addi sp, sp, -{frame_size}",
+	"sd x1, 8(sp)",
+	"sd x3, 24(sp)",
+	"sd x4, 32(sp)",
+	"sd x5, 40(sp)",
+	"sd x6, 48(sp)",
+	"sd x7, 56(sp)",
+	"sd x8, 64(sp)",
+	"sd x9, 72(sp)",
+	"sd x10, 80(sp)",
+	"sd x11, 88(sp)",
+	"sd x12, 96(sp)",
+	"sd x13, 104(sp)",
+	"sd x14, 112(sp)",
+	"sd x15, 120(sp)",
+	"sd x16, 128(sp)",
+	"sd x17, 136(sp)",
+	"sd x18, 144(sp)",
+	"sd x19, 152(sp)",
+	"sd x20, 160(sp)",
+	"sd x21, 168(sp)",
+	"sd x22, 176(sp)",
+	"sd x23, 184(sp)",
+	"sd x24, 192(sp)",
+	"sd x25, 200(sp)",
+	"sd x26, 208(sp)",
+	"sd x27, 216(sp)",
+	"sd x28, 224(sp)",
+	"sd x29, 232(sp)",
+	"sd x30, 240(sp)",
+	"sd x31, 248(sp)",
+	"addi t0, sp, {frame_size}",
+	"sd t0, 16(sp)",
+	"sd zero, 0(sp)",
+	"csrr t0, sepc",
+	"sd t0, 256(sp)",
+	"csrr t0, scause",
+	"sd t0, 264(sp)",
+	"csrr t0, stval",
+	"sd t0, 272(sp)",
+	"csrr t0, sstatus",
+	"sd t0, 280(sp)",
+	"mv a0, sp",
+	"call {handle_exception}",
+	"ebreak",
+	frame_size = const core::mem::size_of::<ExceptionFrame>(),
+	handle_exception = sym handle_exception,
+);
+
+extern "C" {
+	static exception_entry: u8;
+}
+
+/// The stack of `exception_entry`.
+#[repr(C)]
+struct ExceptionFrame {
+	x: [u64; 32],
+	sepc: u64,
+	scause: u64,
+	stval: u64,
+	sstatus: u64,
+}
+
+impl ExceptionFrame {
+	/// The ABI names of the general registers.
+	const NAMES: [&'static str; 32] = [
+		"zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+		"a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+		"t5", "t6",
+	];
+
+	fn is_interrupt(&self) -> bool {
+		self.scause >> 63 != 0
+	}
+
+	fn name(&self) -> &'static str {
+		if self.is_interrupt() {
+			return "Interrupt";
+		}
+
+		match self.scause {
+			0 => "Instruction address misaligned",
+			1 => "Instruction access fault",
+			2 => "Illegal instruction",
+			3 => "Breakpoint",
+			4 => "Load address misaligned",
+			5 => "Load access fault",
+			6 => "Store/AMO address misaligned",
+			7 => "Store/AMO access fault",
+			8 => "Environment call from U-mode",
+			9 => "Environment call from S-mode",
+			12 => "Instruction page fault",
+			13 => "Load page fault",
+			15 => "Store/AMO page fault",
+			18 => "Software check",
+			19 => "Hardware error",
+			_ => "Reserved exception",
+		}
+	}
+}
+
+impl fmt::Display for ExceptionFrame {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} (scause = {:#x}, stval = {:#x}) at {:#x}",
+			self.name(),
+			self.scause,
+			self.stval,
+			self.sepc
+		)?;
+
+		for (names, values) in Self::NAMES.chunks(4).zip(self.x.chunks(4)) {
+			writeln!(f)?;
+			for (name, value) in names.iter().zip(values) {
+				write!(f, " {name:>4}={value:#018x}")?;
+			}
+		}
+		write!(
+			f,
+			"\n   pc={:#018x} sstatus={:#018x}",
+			self.sepc, self.sstatus
+		)
+	}
+}
+
+extern "C" fn handle_exception(frame: &ExceptionFrame) -> ! {
+	// x8 is the frame pointer.
+	let backtrace = Backtrace::interrupted(frame.sepc, frame.x[8]);
+	crate::fatal::report(format_args!("{frame}\n{backtrace}"))
+}
+
+/// Installs the exception handler in `stvec`.
+pub fn install_exception_handlers() {
+	let entry = ptr::addr_of!(exception_entry).addr();
+	unsafe {
+		asm!("csrw stvec, {}", in(reg) entry, options(nomem, nostack));
+	}
+}
@@ -1,10 +1,16 @@
 mod console;
 pub use self::console::Console;
 mod address_range;
+mod exception;
+pub use self::exception::install_exception_handlers;
+mod shutdown;
 mod start;
-
+use alloc::vec;
 use core::arch::asm;
-use core::{mem, slice};
+use core::convert::Infallible;
+use core::mem::MaybeUninit;
+use core::ops::Range;
+use core::{mem, ptr, slice};
 
 use address_range::AddressRange;
 use fdt::node::FdtNode;
@@ -16,7 +22,32 @@ use hermit_entry::Entry;
 use log::info;
 use sptr::Strict;
 
-use crate::BootInfoExt;
+pub use self::shutdown::{debug_exit, halt, poweroff, reboot};
+use crate::error::LoaderError;
+use crate::{cmdline, BootInfoExt};
+
+extern "C" {
+	static loader_start: u8;
+	static loader_end: u8;
+}
+
+const PROTOCOL: &str = "devicetree";
+
+/// The size of the heap, which mainly holds the copy of the devicetree.
+const HEAP_SIZE: usize = 0x10_0000;
+
+/// The free space in the copy of the devicetree for the loader's properties,
+/// in addition to the space for the command line and the environment variables.
+const FDT_HEADROOM: usize = 0x1000;
+
+/// Switches the global allocator to a statically allocated heap.
+fn init_heap() {
+	const BYTE: MaybeUninit<u8> = MaybeUninit::uninit();
+	take_static::take_static! {
+		static HEAP: [MaybeUninit<u8>; HEAP_SIZE] = [BYTE; HEAP_SIZE];
+	}
+	crate::os::init_heap(HEAP.take().unwrap());
+}
 
 fn find_kernel_linux(chosen: &FdtNode<'_, '_>) -> Option<&'static [u8]> {
 	let initrd_start = chosen.property("linux,initrd-start")?.as_usize()?;
@@ -41,7 +72,10 @@ fn find_kernel_multiboot(chosen: &FdtNode<'_, '_>) -> Option<&'static [u8]> {
 					.any(|compatible| compatible == "multiboot,ramdisk")
 			})
 		})?;
-	let reg = module.property("reg").unwrap();
+	let reg = module.property("reg")?;
+	if reg.value.len() != 2 * mem::size_of::<usize>() {
+		return None;
+	}
 	let addr = usize::from_be_bytes(reg.value[..mem::size_of::<usize>()].try_into().unwrap());
 	let len = usize::from_be_bytes(reg.value[mem::size_of::<usize>()..].try_into().unwrap());
 
@@ -50,18 +84,48 @@ fn find_kernel_multiboot(chosen: &FdtNode<'_, '_>) -> Option<&'static [u8]> {
 	Some(unsafe { slice::from_raw_parts(initrd_start, len) })
 }
 
-pub fn find_kernel() -> &'static [u8] {
+/// Returns the current value of the `time` CSR.
+pub fn timestamp() -> u64 {
+	let time: u64;
+	unsafe {
+		asm!("rdtime {}", out(reg) time, options(nomem, nostack));
+	}
+	time
+}
+
+/// Returns the frequency of the `time` CSR from `/cpus/timebase-frequency`.
+pub fn timestamp_frequency() -> Option<u64> {
+	let frequency = start::get_fdt()
+		.find_node("/cpus")?
+		.property("timebase-frequency")?
+		.as_usize()?;
+	u64::try_from(frequency).ok()
+}
+
+/// Returns the command line from `/chosen/bootargs`.
+pub fn command_line() -> Option<&'static str> {
+	start::get_fdt()
+		.find_node("/chosen")?
+		.property("bootargs")?
+		.as_str()
+}
+
+pub fn find_kernel() -> Result<&'static [u8], LoaderError> {
 	let fdt = start::get_fdt();
-	let chosen = fdt.find_node("/chosen").unwrap();
-	find_kernel_linux(&chosen)
-		.or_else(|| find_kernel_multiboot(&chosen))
-		.expect("could not find kernel")
+	fdt.find_node("/chosen")
+		.and_then(|chosen| find_kernel_linux(&chosen).or_else(|| find_kernel_multiboot(&chosen)))
+		.ok_or(LoaderError::BootProtocol {
+			protocol: PROTOCOL,
+			addr: Some(start::get_fdt_ptr().expose_addr() as u64),
+			expected: "the kernel in /chosen/linux,initrd-start or a /chosen/module@<addr> compatible with multiboot,ramdisk",
+		})
 }
 
 pub unsafe fn get_memory(memory_size: u64) -> u64 {
 	let memory_size = usize::try_from(memory_size).unwrap();
 
-	let initrd = AddressRange::try_from(find_kernel().as_ptr_range()).unwrap();
+	// The kernel has already been found before.
+	let initrd = AddressRange::try_from(find_kernel().unwrap().as_ptr_range()).unwrap();
 	let fdt = {
 		let start = start::get_fdt_ptr();
 		let end = unsafe { start.add(start::get_fdt().total_size()) };
@@ -90,23 +154,116 @@ pub unsafe fn get_memory(memory_size: u64) -> u64 {
 	u64::try_from(start_address).unwrap()
 }
 
-pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
+/// Returns a random address for a relocatable kernel in the RAM from the devicetree.
+pub unsafe fn get_random_memory(memory_size: u64) -> Option<u64> {
+	let fdt = start::get_fdt();
+	let to_range = |range: Range<*const u8>| range.start.addr() as u64..range.end.addr() as u64;
+
+	let loader = to_range(ptr::addr_of!(loader_start)..ptr::addr_of!(loader_end));
+	let initrd = to_range(find_kernel().ok()?.as_ptr_range());
+	let fdt_range = {
+		let start = start::get_fdt_ptr();
+		to_range(start..start.wrapping_add(fdt.total_size()))
+	};
+
+	let memory = fdt.memory();
+	let usable = || {
+		memory.regions().filter_map(|region| {
+			let start = region.starting_address.addr() as u64;
+			Some(start..start + u64::try_from(region.size?).unwrap())
+		})
+	};
+	let reserved = || {
+		let reservations = fdt.memory_reservations().map(|reservation| {
+			let start = reservation.address().addr() as u64;
+			start..start + u64::try_from(reservation.size()).unwrap()
+		});
+		let reserved_memory = fdt
+			.find_node("/reserved-memory")
+			.into_iter()
+			.flat_map(|node| node.children())
+			.flat_map(|node| node.reg().into_iter().flatten())
+			.filter_map(|region| {
+				let start = region.starting_address.addr() as u64;
+				Some(start..start + u64::try_from(region.size?).unwrap())
+			});
+
+		[loader.clone(), initrd.clone(), fdt_range.clone()]
+			.into_iter()
+			.chain(reservations)
+			.chain(reserved_memory)
+	};
+
+	crate::kaslr::choose(usable, reserved, memory_size, crate::entropy::kaslr_seed)
+}
+
+/// Returns a random seed from `/chosen/kaslr-seed` or `/chosen/rng-seed`.
+pub fn firmware_seed() -> Option<u64> {
+	let fdt = start::get_fdt();
+	let chosen = fdt.find_node("/chosen")?;
+	crate::kaslr::fdt_seed(
+		chosen.property("kaslr-seed").map(|property| property.value),
+		chosen.property("rng-seed").map(|property| property.value),
+	)
+}
+
+/// Returns a random number from the processor.
+///
+/// The `seed` CSR of the Zkr extension is not accessible in supervisor mode by default, so this always fails.
+pub fn random_u64() -> Option<u64> {
+	None
+}
+
+/// Returns the detached signature of the kernel.
+///
+/// Only signatures that are appended to the kernel are supported.
+pub fn detached_signature() -> Option<&'static [u8]> {
+	None
+}
+
+pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> Result<Infallible, LoaderError> {
 	let LoadedKernel {
 		load_info,
 		entry_point,
 	} = kernel_info;
 
+	{
+		let cmdline = cmdline::edited().or_else(command_line);
+		let env = crate::fdt_patch::env(cmdline);
+
+		// We do not know whether the memory behind the devicetree is free,
+		// so the devicetree is copied to the heap to make room for the loader's properties.
+		let len = start::get_fdt().total_size();
+		let firmware_fdt =
+			unsafe { slice::from_raw_parts_mut(start::get_fdt_ptr().cast_mut(), len) };
+		let headroom = FDT_HEADROOM + cmdline.map_or(0, str::len) + env.len();
+		let fdt = vec![0; len + headroom].leak();
+		fdt[..len].copy_from_slice(firmware_fdt);
+		// The seeds in the firmware's devicetree may have selected the kernel address.
+		for name in ["kaslr-seed", "rng-seed"] {
+			if let Some(seed) = crate::fdt_edit::chosen_property_mut(firmware_fdt, name) {
+				seed.fill(0);
+			}
+		}
+
+		crate::fdt_patch::patch(fdt, &env);
+		start::set_fdt_ptr(fdt.as_ptr());
+	}
+
 	let fdt = start::get_fdt();
 
 	let phys_addr_range = {
 		let memory = fdt.memory();
 		let mut regions = memory.regions();
 
-		let mem_region = regions.next().unwrap();
-		assert!(
-			regions.next().is_none(),
-			"hermit-loader can only handle one memory region yet"
-		);
+		let mem_region = regions
+			.next()
+			.filter(|mem_region| mem_region.size.is_some())
+			.filter(|_| regions.next().is_none())
+			.ok_or(LoaderError::Memory {
+				protocol: PROTOCOL,
+				expected: "exactly one memory region with a size",
+			})?;
 
 		let mem_base = u64::try_from(mem_region.starting_address.addr()).unwrap();
 		let mem_size = u64::try_from(mem_region.size.unwrap()).unwrap();
@@ -132,6 +289,9 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 	let entry = sptr::from_exposed_addr(entry_point.try_into().unwrap());
 	let hart_id = start::get_hart_id();
 	let raw_boot_info = boot_info.write();
+	let len = start::get_fdt().total_size();
+	let fdt = unsafe { slice::from_raw_parts_mut(start::get_fdt_ptr().cast_mut(), len) };
+	crate::boot_time::finish(Some(fdt));
 
 	unsafe { enter_kernel(stack, entry, hart_id, raw_boot_info) }
 }
@@ -0,0 +1,31 @@
+//! Stopping the machine after fatal errors.
+
+use core::arch::asm;
+
+use sbi_rt::{ColdReboot, Shutdown, SystemFailure};
+
+pub fn halt() -> ! {
+	loop {
+		unsafe {
+			asm!("wfi", options(nomem, nostack));
+		}
+	}
+}
+
+pub fn reboot() -> ! {
+	sbi_rt::system_reset(ColdReboot, SystemFailure);
+	halt()
+}
+
+/// Turns off the machine using the SBI system reset extension.
+///
+/// OpenSBI reports the system failure to QEMU, which exits with a failure status.
+pub fn poweroff() -> ! {
+	sbi_rt::system_reset(Shutdown, SystemFailure);
+	halt()
+}
+
+/// Exits QEMU with a failure status, which [`poweroff`] already does using OpenSBI.
+pub fn debug_exit() -> ! {
+	poweroff()
+}
@@ -15,6 +15,11 @@ pub fn get_fdt_ptr() -> *const u8 {
 	FDT.load(Ordering::Relaxed).cast_const()
 }
 
+/// Replaces the devicetree from the firmware, e.g., with a copy that has room to grow.
+pub fn set_fdt_ptr(fdt: *const u8) {
+	FDT.store(fdt.cast_mut(), Ordering::Relaxed);
+}
+
 pub fn get_fdt() -> Fdt<'static> {
 	// SAFETY: We trust the FDT pointer provided by the firmware
 	unsafe { Fdt::from_ptr(get_fdt_ptr()).unwrap() }
@@ -40,6 +45,8 @@ pub unsafe extern "C" fn _start(hart_id: usize, fdt: *const u8) -> ! {
 		"la      sp, {BOOT_STACK}",
 		"li      t0, 0x8000",
 		"add     sp, sp, t0",
+		// Terminate the frame pointer chain for backtraces
+		"li      s0, 0",
 
 		"j       {start}",
 
@@ -51,6 +58,7 @@ pub unsafe extern "C" fn _start(hart_id: usize, fdt: *const u8) -> ! {
 extern "C" fn start(hart_id: usize, fdt: *const u8) -> ! {
 	HART_ID.store(hart_id, Ordering::Relaxed);
 	FDT.store(fdt.cast_mut(), Ordering::Relaxed);
+	super::init_heap();
 
 	unsafe { crate::os::loader_main() }
 }
@@ -0,0 +1,271 @@
+//! Minimal ACPI table parsing.
+//!
+//! See [5. ACPI Software Programming Model — ACPI Specification 6.5 documentation](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html) for details.
+
+use alloc::vec::Vec;
+use core::{mem, slice};
+
+use log::info;
+#[cfg(target_os = "none")]
+use log::warn;
+
+use crate::fdt::{Cpu, IoApic};
+
+#[cfg(target_os = "none")]
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// Root System Description Pointer (RSDP) structure.
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct Rsdp {
+	signature: [u8; 8],
+	checksum: u8,
+	oem_id: [u8; 6],
+	revision: u8,
+	rsdt_address: u32,
+	length: u32,
+	xsdt_address: u64,
+	extended_checksum: u8,
+	reserved: [u8; 3],
+}
+
+impl Rsdp {
+	/// Size of the structure as defined in ACPI 1.0.
+	#[cfg(target_os = "none")]
+	const V1_LEN: usize = 20;
+
+	/// Returns the ACPI major version of the RSDP at `addr` if it is valid.
+	#[cfg(target_os = "none")]
+	fn validate(addr: usize) -> Option<u8> {
+		map(addr, Self::V1_LEN);
+		let bytes = unsafe { slice::from_raw_parts(sptr::from_exposed_addr(addr), Self::V1_LEN) };
+		if &bytes[..RSDP_SIGNATURE.len()] != RSDP_SIGNATURE || !is_checksum_valid(bytes) {
+			return None;
+		}
+
+		let rsdp = unsafe { Self::read(addr) };
+		if rsdp.revision < 2 {
+			return Some(1);
+		}
+
+		// The RSDP has a fixed length, so other lengths indicate a corrupt RSDP.
+		let len = rsdp.length as usize;
+		if len != mem::size_of::<Self>() {
+			return None;
+		}
+
+		map(addr, len);
+		let bytes = unsafe { slice::from_raw_parts(sptr::from_exposed_addr(addr), len) };
+		is_checksum_valid(bytes).then_some(2)
+	}
+
+	/// Reads the RSDP at `addr`.
+	///
+	/// For ACPI 1.0 RSDPs, only the fields up to `rsdt_address` are meaningful.
+	unsafe fn read(addr: usize) -> Self {
+		map(addr, mem::size_of::<Self>());
+		unsafe { sptr::from_exposed_addr::<Self>(addr).read_unaligned() }
+	}
+}
+
+/// An upper bound for the length of a System Description Table, which protects against corrupt headers.
+const MAX_SDT_LEN: usize = 0x10_0000;
+
+/// System Description Table header.
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct SdtHeader {
+	signature: [u8; 4],
+	length: u32,
+	revision: u8,
+	checksum: u8,
+	oem_id: [u8; 6],
+	oem_table_id: [u8; 8],
+	oem_revision: u32,
+	creator_id: u32,
+	creator_revision: u32,
+}
+
+/// Makes sure that `len` bytes of physical memory at `addr` can be read.
+fn map(addr: usize, len: usize) {
+	cfg_if::cfg_if! {
+		if #[cfg(target_os = "none")] {
+			use x86_64::structures::paging::PageTableFlags;
+
+			super::paging::map_identity(addr, addr + len, PageTableFlags::empty());
+		} else {
+			// UEFI identity-maps all memory.
+			let _ = (addr, len);
+		}
+	}
+}
+
+/// Returns whether the bytes of a firmware table sum up to 0.
+///
+/// This is used by ACPI and MultiProcessor Specification tables.
+pub fn is_checksum_valid(bytes: &[u8]) -> bool {
+	bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Reads the table header at `addr`.
+fn header(addr: usize) -> SdtHeader {
+	map(addr, mem::size_of::<SdtHeader>());
+	unsafe { sptr::from_exposed_addr::<SdtHeader>(addr).read_unaligned() }
+}
+
+/// Returns the validated table at `addr` including its header.
+fn table(addr: usize) -> Option<&'static [u8]> {
+	let len = header(addr).length as usize;
+	if !(mem::size_of::<SdtHeader>()..=MAX_SDT_LEN).contains(&len) {
+		return None;
+	}
+
+	map(addr, len);
+	let table = unsafe { slice::from_raw_parts(sptr::from_exposed_addr(addr), len) };
+	is_checksum_valid(table).then_some(table)
+}
+
+/// Returns the table with `signature` from the RSDT or XSDT referenced by the RSDP at `rsdp`.
+fn find_table(rsdp: u64, signature: &[u8; 4]) -> Option<&'static [u8]> {
+	let rsdp = unsafe { Rsdp::read(rsdp.try_into().unwrap()) };
+
+	let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+		(table(rsdp.xsdt_address.try_into().unwrap())?, 8)
+	} else {
+		(table(rsdp.rsdt_address.try_into().unwrap())?, 4)
+	};
+
+	root[mem::size_of::<SdtHeader>()..]
+		.chunks_exact(entry_size)
+		.map(|entry| match *entry {
+			[a, b, c, d] => u64::from(u32::from_le_bytes([a, b, c, d])),
+			_ => u64::from_le_bytes(entry.try_into().unwrap()),
+		})
+		.filter_map(|addr| usize::try_from(addr).ok())
+		// Only tables with the signature are mapped as a whole.
+		.filter(|addr| header(*addr).signature == *signature)
+		.find_map(table)
+}
+
+/// Returns the entries of the Multiple APIC Description Table (MADT) as `(type, entry)` pairs.
+fn madt_entries(rsdp: u64) -> Option<impl Iterator<Item = (u8, &'static [u8])>> {
+	let madt = find_table(rsdp, b"APIC")?;
+
+	// The header is followed by the local interrupt controller address and flags.
+	let mut entries = madt.get(mem::size_of::<SdtHeader>() + 8..)?;
+
+	Some(core::iter::from_fn(move || {
+		let [ty, len, ..] = *entries else {
+			return None;
+		};
+		let len = usize::from(len);
+		if len < 2 || len > entries.len() {
+			return None;
+		}
+
+		let (entry, rest) = entries.split_at(len);
+		entries = rest;
+		Some((ty, entry))
+	}))
+}
+
+/// Returns the CPUs described by the processor local (x2)APIC entries of the MADT.
+pub fn cpus(rsdp: u64) -> Option<Vec<Cpu>> {
+	const PROCESSOR_LOCAL_APIC: u8 = 0;
+	const PROCESSOR_LOCAL_X2APIC: u8 = 9;
+	const ENABLED: u32 = 1 << 0;
+
+	let read_u32 = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+
+	let cpus = madt_entries(rsdp)?
+		.filter_map(|(ty, entry)| match ty {
+			PROCESSOR_LOCAL_APIC if entry.len() >= 8 => Some(Cpu {
+				id: u32::from(entry[3]),
+				enabled: read_u32(&entry[4..8]) & ENABLED != 0,
+			}),
+			PROCESSOR_LOCAL_X2APIC if entry.len() >= 16 => Some(Cpu {
+				id: read_u32(&entry[4..8]),
+				enabled: read_u32(&entry[8..12]) & ENABLED != 0,
+			}),
+			_ => None,
+		})
+		.collect::<Vec<_>>();
+
+	info!("Found {} CPU(s) in the MADT", cpus.len());
+	Some(cpus)
+}
+
+/// Returns the I/O APICs described by the MADT.
+pub fn io_apics(rsdp: u64) -> Option<Vec<IoApic>> {
+	const IO_APIC: u8 = 1;
+
+	let io_apics = madt_entries(rsdp)?
+		.filter(|(ty, entry)| *ty == IO_APIC && entry.len() >= 12)
+		.map(|(_, entry)| IoApic {
+			id: entry[2],
+			addr: u64::from(u32::from_le_bytes(entry[4..8].try_into().unwrap())),
+			gsi_base: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+		})
+		.collect();
+
+	Some(io_apics)
+}
+
+/// Returns the base address of the HPET from the HPET Description Table.
+pub fn hpet_address(rsdp: u64) -> Option<u64> {
+	const SYSTEM_MEMORY: u8 = 0;
+
+	let hpet = find_table(rsdp, b"HPET")?;
+
+	// The header and the event timer block ID are followed by the base address as Generic Address Structure.
+	let address = hpet.get(mem::size_of::<SdtHeader>() + 4..mem::size_of::<SdtHeader>() + 16)?;
+	if address[0] != SYSTEM_MEMORY {
+		return None;
+	}
+
+	let address = u64::from_le_bytes(address[4..].try_into().unwrap());
+	(address != 0).then_some(address)
+}
+
+/// Returns `rsdp` if it points to a valid RSDP.
+///
+/// This is used for RSDP addresses that are passed by the boot protocol.
+#[cfg(target_os = "none")]
+#[cfg_attr(not(feature = "fc"), expect(unused))]
+pub fn check_rsdp(rsdp: u64) -> Option<u64> {
+	let addr = usize::try_from(rsdp).ok()?;
+
+	match Rsdp::validate(addr) {
+		Some(version) => {
+			info!("Found ACPI {version} RSDP at {rsdp:#x}");
+			Some(rsdp)
+		}
+		None => {
+			warn!("Ignoring invalid RSDP at {rsdp:#x}");
+			None
+		}
+	}
+}
+
+/// Searches the EBDA and the BIOS area for the RSDP.
+///
+/// See [5.2.5.1. Finding the RSDP on IA-PC Systems — ACPI Specification 6.5 documentation](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html#finding-the-rsdp-on-ia-pc-systems) for details.
+#[cfg(target_os = "none")]
+pub fn search_rsdp() -> Option<u64> {
+	use super::bios;
+
+	let (rsdp, version) = bios::ebda()
+		.into_iter()
+		.chain([bios::BIOS_AREA])
+		.find_map(|area| {
+			// The RSDP is always located on a 16-byte boundary.
+			bios::search(area, 16, Rsdp::V1_LEN, |addr| {
+				Rsdp::validate(addr).map(|version| (addr, version))
+			})
+		})?;
+
+	info!("Found ACPI {version} RSDP at {rsdp:#x}");
+	Some(rsdp as u64)
+}
@@ -0,0 +1,62 @@
+//! Helpers for searching firmware structures in legacy BIOS memory.
+
+use core::ops::Range;
+
+use x86_64::structures::paging::{PageTableFlags, Size4KiB};
+
+use super::paging;
+
+/// Location of the real-mode segment of the Extended BIOS Data Area (EBDA) in the BIOS Data Area.
+const EBDA_SEGMENT_ADDRESS: usize = 0x40E;
+
+/// Location of the size of the base memory in KiB in the BIOS Data Area.
+const BASE_MEMORY_SIZE_ADDRESS: usize = 0x413;
+
+/// The read-only BIOS area.
+pub const BIOS_AREA: Range<usize> = 0xE0000..0x100000;
+
+/// Reads a `u16` from the BIOS Data Area.
+fn read_bda(addr: usize) -> u16 {
+	// The BIOS Data Area lives in the first page, which we do not want to keep mapped.
+	let was_mapped = paging::is_mapped(0);
+	if !was_mapped {
+		paging::map::<Size4KiB>(0, 0, 1, PageTableFlags::empty());
+	}
+
+	let value = unsafe { sptr::from_exposed_addr::<u16>(addr).read_unaligned() };
+
+	if !was_mapped {
+		paging::unmap::<Size4KiB>(0, 1);
+	}
+
+	value
+}
+
+/// Returns the first KiB of the Extended BIOS Data Area (EBDA).
+pub fn ebda() -> Option<Range<usize>> {
+	let start = usize::from(read_bda(EBDA_SEGMENT_ADDRESS)) << 4;
+	(start != 0 && start < BIOS_AREA.start).then_some(start..start + 0x400)
+}
+
+/// Returns the last KiB of the base memory.
+pub fn base_memory_end() -> Option<Range<usize>> {
+	let end = usize::from(read_bda(BASE_MEMORY_SIZE_ADDRESS)) * 0x400;
+	(0x400..=BIOS_AREA.start)
+		.contains(&end)
+		.then_some(end - 0x400..end)
+}
+
+/// Identity-maps `area` and searches it for a structure of `len` bytes on `align`-byte boundaries.
+pub fn search<T>(
+	area: Range<usize>,
+	align: usize,
+	len: usize,
+	f: impl FnMut(usize) -> Option<T>,
+) -> Option<T> {
+	paging::map_identity(area.start, area.end, PageTableFlags::empty());
+
+	let end = area.end;
+	area.step_by(align)
+		.take_while(|addr| addr + len <= end)
+		.find_map(f)
+}
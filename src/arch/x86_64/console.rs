@@ -1,21 +1,284 @@
+use one_shot_mutex::OneShotMutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
 
+#[cfg(not(feature = "fc"))]
+use super::framebuffer::Framebuffer;
+#[cfg(not(feature = "fc"))]
+use super::text::TextConsole;
+#[cfg(all(target_os = "none", not(feature = "fc")))]
+use super::vga::VgaText;
+use crate::cmdline;
+
+/// A serial port configuration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Serial {
+	pub port: u16,
+	pub baud_rate: u32,
+}
+
+impl Serial {
+	/// I/O ports of `ttyS0` to `ttyS3`.
+	const PORTS: [u16; 4] = [0x3F8, 0x2F8, 0x3E8, 0x2E8];
+
+	/// Baud rate set up by [`SerialPort::init`].
+	const DEFAULT_BAUD_RATE: u32 = 38400;
+
+	const COM1: Self = Self {
+		port: Self::PORTS[0],
+		baud_rate: Self::DEFAULT_BAUD_RATE,
+	};
+
+	/// Parses `ttyS<N>[,<baud>]`.
+	fn parse(s: &str) -> Option<Self> {
+		let (tty, options) = s.split_once(',').unwrap_or((s, ""));
+		let index: usize = tty.strip_prefix("ttyS")?.parse().ok()?;
+		let port = *Self::PORTS.get(index)?;
+
+		// The baud rate may be followed by parity, bits, and flow control, e.g., `115200n8`.
+		let baud_rate = match options.split(|c: char| !c.is_ascii_digit()).next() {
+			Some("") | None => Self::DEFAULT_BAUD_RATE,
+			Some(baud_rate) => baud_rate.parse().ok()?,
+		};
+		if baud_rate == 0 || 115_200 % baud_rate != 0 {
+			return None;
+		}
+
+		Some(Self { port, baud_rate })
+	}
+
+	/// Returns the ISA interrupt of the serial port.
+	pub fn irq(&self) -> u32 {
+		match self.port {
+			0x2F8 | 0x2E8 => 3,
+			_ => 4,
+		}
+	}
+
+	/// Initializes the serial port with the baud rate.
+	fn init(&self) -> SerialPort {
+		const LINE_CONTROL: u16 = 3;
+		const DLAB: u8 = 0x80;
+		const DATA_8N1: u8 = 0x03;
+
+		let mut serial_port = unsafe { SerialPort::new(self.port) };
+		serial_port.init();
+
+		let divisor = u16::try_from(115_200 / self.baud_rate).unwrap();
+		let mut data = Port::<u8>::new(self.port);
+		let mut interrupt_enable = Port::<u8>::new(self.port + 1);
+		let mut line_control = Port::<u8>::new(self.port + LINE_CONTROL);
+		unsafe {
+			let interrupts = interrupt_enable.read();
+			line_control.write(DLAB);
+			data.write(divisor as u8);
+			interrupt_enable.write((divisor >> 8) as u8);
+			line_control.write(DATA_8N1);
+			interrupt_enable.write(interrupts);
+		}
+
+		serial_port
+	}
+
+	/// Returns whether a UART answers at the port in loopback mode.
+	fn loopback_test(&self) -> bool {
+		const MODEM_CONTROL: u16 = 4;
+		const LINE_STATUS: u16 = 5;
+		const LOOPBACK: u8 = 0x10;
+		const DATA_READY: u8 = 0x01;
+		const TEST_BYTE: u8 = 0xAE;
+		/// The maximum number of polls for the looped-back byte, which arrives after one character time.
+		const MAX_POLLS: u32 = 1_000_000;
+
+		let mut data = Port::<u8>::new(self.port);
+		let mut modem_control = Port::<u8>::new(self.port + MODEM_CONTROL);
+		let mut line_status = Port::<u8>::new(self.port + LINE_STATUS);
+		let mut data_ready = || unsafe { line_status.read() } & DATA_READY != 0;
+
+		unsafe {
+			let modem = modem_control.read();
+			modem_control.write(modem | LOOPBACK);
+
+			// Discard received bytes.
+			for _ in 0..16 {
+				if !data_ready() {
+					break;
+				}
+				data.read();
+			}
+
+			data.write(TEST_BYTE);
+			let answer = (0..MAX_POLLS).any(|_| data_ready()).then(|| data.read());
+			modem_control.write(modem);
+			answer == Some(TEST_BYTE)
+		}
+	}
+}
+
+/// The sinks the console writes to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Sinks {
+	serial: bool,
+	debugcon: bool,
+	vga: bool,
+	framebuffer: bool,
+}
+
+impl Sinks {
+	const ALL: Self = Self {
+		serial: true,
+		debugcon: true,
+		vga: true,
+		framebuffer: true,
+	};
+
+	const NONE: Self = Self {
+		serial: false,
+		debugcon: false,
+		vga: false,
+		framebuffer: false,
+	};
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Config {
+	serial: Serial,
+	sinks: Sinks,
+}
+
+static CONFIG: OneShotMutex<Config> = OneShotMutex::new(Config {
+	serial: Serial::COM1,
+	sinks: Sinks::ALL,
+});
+
+/// Returns the serial port used by the console.
+pub fn serial() -> Serial {
+	CONFIG.lock().serial
+}
+
+/// Configures the console from `loader.console=` or `console=` in `cmdline`.
+///
+/// `loader.console=` may be given multiple times and selects the sinks of the console:
+/// `ttyS<N>[,<baud>]`, `debugcon`, `vga`, or `fb`.
+/// Without it, all available sinks are used with the serial port from `console=`.
+///
+/// This has to be called before anything is printed.
+/// Returns the requested serial port if it does not exist.
+pub fn configure(cmdline: &str) -> Result<(), Serial> {
+	let mut sinks = Sinks::NONE;
+	let mut serial = None;
+	for value in cmdline::values(cmdline, "loader.console") {
+		match value {
+			"debugcon" => sinks.debugcon = true,
+			"vga" => sinks.vga = true,
+			"fb" => sinks.framebuffer = true,
+			value => {
+				if let Some(value) = Serial::parse(value) {
+					sinks.serial = true;
+					serial = Some(value);
+				}
+			}
+		}
+	}
+
+	let mut config = CONFIG.lock();
+	if sinks != Sinks::NONE {
+		config.sinks = sinks;
+	}
+
+	let Some(serial) = serial.or_else(|| {
+		cmdline::values(cmdline, "console")
+			.filter_map(Serial::parse)
+			.last()
+	}) else {
+		return Ok(());
+	};
+
+	serial.init();
+	if !serial.loopback_test() {
+		return Err(serial);
+	}
+
+	config.serial = serial;
+	Ok(())
+}
+
+/// Returns the QEMU and Bochs debug console port if it exists.
+fn debugcon() -> Option<Port<u8>> {
+	const DEBUGCON_PORT: u16 = 0xE9;
+
+	let mut port = Port::new(DEBUGCON_PORT);
+	// Reading the port returns its number if the debug console exists.
+	(unsafe { port.read() } == 0xE9).then_some(port)
+}
+
+/// A console writing to all enabled sinks.
 pub struct Console {
-	serial_port: SerialPort,
+	serial_port: Option<SerialPort>,
+	debugcon: Option<Port<u8>>,
+	#[cfg(all(target_os = "none", not(feature = "fc")))]
+	vga: Option<TextConsole<VgaText>>,
+	#[cfg(not(feature = "fc"))]
+	framebuffer: Option<TextConsole<Framebuffer>>,
 }
 
 impl Console {
 	pub fn write_bytes(&mut self, bytes: &[u8]) {
-		for byte in bytes.iter().copied() {
-			self.serial_port.send(byte);
+		if let Some(serial_port) = &mut self.serial_port {
+			for byte in bytes.iter().copied() {
+				serial_port.send(byte);
+			}
+		}
+
+		if let Some(debugcon) = &mut self.debugcon {
+			for byte in bytes.iter().copied() {
+				unsafe { debugcon.write(byte) }
+			}
+		}
+
+		#[cfg(all(target_os = "none", not(feature = "fc")))]
+		if let Some(vga) = &mut self.vga {
+			vga.write_bytes(bytes);
+		}
+
+		#[cfg(not(feature = "fc"))]
+		if let Some(framebuffer) = &mut self.framebuffer {
+			framebuffer.write_bytes(bytes);
+		}
+	}
+
+	/// Receives a byte from the serial port if one is available.
+	pub fn read_byte(&mut self) -> Option<u8> {
+		self.serial_port.as_mut()?.try_receive().ok()
+	}
+
+	/// Writes to `framebuffer` instead of VGA text mode from now on.
+	///
+	/// `framebuffer` has to be mapped.
+	#[cfg(not(feature = "fc"))]
+	pub fn set_framebuffer(&mut self, framebuffer: Framebuffer) {
+		#[cfg(target_os = "none")]
+		{
+			self.vga = None;
+		}
+
+		if CONFIG.lock().sinks.framebuffer && framebuffer.is_valid() {
+			self.framebuffer = Some(TextConsole::new(framebuffer));
 		}
 	}
 }
 
 impl Default for Console {
 	fn default() -> Self {
-		let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-		serial_port.init();
-		Self { serial_port }
+		let Config { serial, sinks } = *CONFIG.lock();
+
+		Self {
+			serial_port: sinks.serial.then(|| serial.init()),
+			debugcon: sinks.debugcon.then(debugcon).flatten(),
+			#[cfg(all(target_os = "none", not(feature = "fc")))]
+			vga: sinks.vga.then(VgaText::new).flatten().map(TextConsole::new),
+			#[cfg(not(feature = "fc"))]
+			framebuffer: None,
+		}
 	}
 }
@@ -185,6 +185,8 @@ start64:
     # set default stack pointer
     movabs rsp, OFFSET boot_stack
     add rsp, BOOT_STACK_SIZE-16
+    # terminate the frame pointer chain for backtraces
+    xor ebp, ebp
 
     # jump to the boot processors's C code
     .extern loader_main
@@ -0,0 +1,236 @@
+//! CPU exception handlers that report the exception and stop the machine.
+
+use core::arch::global_asm;
+use core::{fmt, mem, ptr};
+
+use x86_64::instructions::tables::lidt;
+use x86_64::registers::control::Cr2;
+use x86_64::registers::segmentation::{Segment, CS};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+/// The number of exception vectors.
+const EXCEPTIONS: usize = 32;
+
+/// The size of each entry stub in `exception_stubs`.
+const STUB_SIZE: usize = 16;
+
+const PAGE_FAULT: u64 = 14;
+
+// Each stub pushes a zero error code if the CPU does not push one, followed by the vector.
+global_asm!(
+	".section .text",
+	".balign 16",
+	"exception_stubs:",
+	".irp vector, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31",
+	".balign 16",
+	".if !(\\vector == 8 || \\vector == 10 || \\vector == 11 || \\vector == 12 || \\vector == 13 || \\vector == 14 || \\vector == 17 || \\vector == 21 || \\vector == 29 || \\vector == 30)",
+	"push 0",
+	".endif",
+	"push \\vector",
+	"jmp exception_common",
+	".endr",
+	"exception_common:",
+	"push rax",
+	"push rcx",
+	"push rdx",
+	"push rbx",
+	"push rbp",
+	"push rsi",
+	"push rdi",
+	"push r8",
+	"push r9",
+	"push r10",
+	"push r11",
+	"push r12",
+	"push r13",
+	"push r14",
+	"push r15",
+	"mov rdi, rsp",
+	"cld",
+	"call {handle_exception}",
+	"ud2",
+	handle_exception = sym handle_exception,
+);
+
+extern "C" {
+	static exception_stubs: u8;
+}
+
+/// The stack of the exception stubs.
+#[repr(C)]
+struct ExceptionFrame {
+	r15: u64,
+	r14: u64,
+	r13: u64,
+	r12: u64,
+	r11: u64,
+	r10: u64,
+	r9: u64,
+	r8: u64,
+	rdi: u64,
+	rsi: u64,
+	rbp: u64,
+	rbx: u64,
+	rdx: u64,
+	rcx: u64,
+	rax: u64,
+	vector: u64,
+	error_code: u64,
+	rip: u64,
+	cs: u64,
+	rflags: u64,
+	rsp: u64,
+	ss: u64,
+}
+
+impl ExceptionFrame {
+	fn name(&self) -> &'static str {
+		match self.vector {
+			0 => "Divide error",
+			1 => "Debug exception",
+			2 => "Non-maskable interrupt",
+			3 => "Breakpoint",
+			4 => "Overflow",
+			5 => "BOUND range exceeded",
+			6 => "Invalid opcode",
+			7 => "Device not available",
+			8 => "Double fault",
+			9 => "Coprocessor segment overrun",
+			10 => "Invalid TSS",
+			11 => "Segment not present",
+			12 => "Stack-segment fault",
+			13 => "General protection fault",
+			14 => "Page fault",
+			16 => "x87 floating-point error",
+			17 => "Alignment check",
+			18 => "Machine check",
+			19 => "SIMD floating-point exception",
+			20 => "Virtualization exception",
+			21 => "Control protection exception",
+			28 => "Hypervisor injection exception",
+			29 => "VMM communication exception",
+			30 => "Security exception",
+			_ => "Reserved exception",
+		}
+	}
+}
+
+impl fmt::Display for ExceptionFrame {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} (vector {}, error code {:#x}) at {:#x}",
+			self.name(),
+			self.vector,
+			self.error_code,
+			self.rip
+		)?;
+		if self.vector == PAGE_FAULT {
+			write!(f, " accessing {:#x}", Cr2::read_raw())?;
+		}
+
+		let registers = [
+			("rax", self.rax),
+			("rbx", self.rbx),
+			("rcx", self.rcx),
+			("rdx", self.rdx),
+			("rsi", self.rsi),
+			("rdi", self.rdi),
+			("rbp", self.rbp),
+			("rsp", self.rsp),
+			("r8", self.r8),
+			("r9", self.r9),
+			("r10", self.r10),
+			("r11", self.r11),
+			("r12", self.r12),
+			("r13", self.r13),
+			("r14", self.r14),
+			("r15", self.r15),
+			("rip", self.rip),
+			("rflags", self.rflags),
+			("cs", self.cs),
+			("ss", self.ss),
+		];
+		for line in registers.chunks(4) {
+			writeln!(f)?;
+			for (name, value) in line {
+				write!(f, " {name:>6}={value:#018x}")?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+extern "C" fn handle_exception(frame: &ExceptionFrame) -> ! {
+	cfg_if::cfg_if! {
+		if #[cfg(target_os = "none")] {
+			let backtrace = crate::backtrace::Backtrace::interrupted(frame.rip, frame.rbp);
+			crate::fatal::report(format_args!("{frame}\n{backtrace}"))
+		} else {
+			crate::fatal::report(format_args!("{frame}"))
+		}
+	}
+}
+
+/// An interrupt gate descriptor.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Gate {
+	offset_low: u16,
+	selector: u16,
+	options: u16,
+	offset_middle: u16,
+	offset_high: u32,
+	reserved: u32,
+}
+
+impl Gate {
+	const MISSING: Self = Self {
+		offset_low: 0,
+		selector: 0,
+		options: 0,
+		offset_middle: 0,
+		offset_high: 0,
+		reserved: 0,
+	};
+
+	/// Present 64-bit interrupt gate.
+	const OPTIONS: u16 = 0x8E00;
+
+	fn new(handler: u64, selector: u16) -> Self {
+		Self {
+			offset_low: handler as u16,
+			selector,
+			options: Self::OPTIONS,
+			offset_middle: (handler >> 16) as u16,
+			offset_high: (handler >> 32) as u32,
+			reserved: 0,
+		}
+	}
+}
+
+/// Installs an IDT with handlers for all CPU exceptions.
+///
+/// On UEFI, this must only be called after exiting boot services, since the firmware owns the IDT before.
+pub fn install_exception_handlers() {
+	take_static::take_static! {
+		static IDT: [Gate; EXCEPTIONS] = [Gate::MISSING; EXCEPTIONS];
+	}
+
+	let idt = IDT.take().unwrap();
+	let stubs = ptr::addr_of!(exception_stubs).addr() as u64;
+	let selector = CS::get_reg().0;
+	for (vector, gate) in idt.iter_mut().enumerate() {
+		*gate = Gate::new(stubs + (vector * STUB_SIZE) as u64, selector);
+	}
+
+	let pointer = DescriptorTablePointer {
+		limit: (mem::size_of_val(idt) - 1) as u16,
+		base: VirtAddr::from_ptr(idt.as_ptr()),
+	};
+	unsafe {
+		lidt(&pointer);
+	}
+}
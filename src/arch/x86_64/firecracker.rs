@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+use core::convert::Infallible;
 use core::ptr::write_bytes;
 use core::{ptr, slice};
 
@@ -16,9 +18,11 @@ use sptr::Strict;
 use x86_64::structures::paging::{PageSize, PageTableFlags, Size2MiB, Size4KiB};
 
 use super::physicalmem::PhysAlloc;
-use super::{paging, KERNEL_STACK_SIZE, SERIAL_IO_PORT};
+use super::{acpi, console, paging, virtio_mmio, KaslrRegions, KERNEL_STACK_SIZE};
+use crate::error::LoaderError;
 use crate::fdt::Fdt;
-use crate::BootInfoExt;
+use crate::measured_boot::{self, Measurement};
+use crate::{boot_time, cmdline, BootInfoExt};
 
 extern "C" {
 	static mut loader_end: u8;
@@ -29,16 +33,108 @@ mod entry {
 	core::arch::global_asm!(include_str!("entry_fc.s"));
 }
 
-pub fn find_kernel() -> &'static [u8] {
+const PROTOCOL: &str = "Linux boot protocol";
+
+/// Offset of `acpi_rsdp_addr` in `boot_params` (Linux boot protocol 2.14+).
+const ACPI_RSDP_ADDR_OFFSET: usize = 0x070;
+
+/// Returns the command line from `boot_params`.
+///
+/// This may be called before the physical memory allocator is initialized.
+pub fn command_line() -> Option<&'static str> {
+	let params = unsafe { boot_params };
+	if params == 0
+		|| !paging::try_map_identity(
+			params,
+			params + Size4KiB::SIZE as usize,
+			PageTableFlags::empty(),
+		) {
+		return None;
+	}
+
+	let cmdline_ptr = unsafe {
+		sptr::from_exposed_addr::<u32>(params + LINUX_SETUP_HEADER_OFFSET + CMD_LINE_PTR_OFFSET)
+			.read_unaligned()
+	} as usize;
+	let cmdline_size = unsafe {
+		sptr::from_exposed_addr::<u32>(params + LINUX_SETUP_HEADER_OFFSET + CMD_LINE_SIZE_OFFSET)
+			.read_unaligned()
+	} as usize;
+
+	if cmdline_ptr == 0
+		|| cmdline_size == 0
+		|| !paging::try_map_identity(
+			cmdline_ptr,
+			cmdline_ptr + cmdline_size,
+			PageTableFlags::empty(),
+		) {
+		return None;
+	}
+
+	let slice =
+		unsafe { core::slice::from_raw_parts(sptr::from_exposed_addr(cmdline_ptr), cmdline_size) };
+	let s = core::str::from_utf8(slice).ok()?.trim_end_matches('\0');
+
+	if s.is_empty() {
+		None
+	} else {
+		Some(s)
+	}
+}
+
+/// Returns the start, size, and type of the e820 entries in `boot_params`.
+fn e820_entries() -> impl Iterator<Item = (u64, u64, u32)> {
+	let linux_e820_entries =
+		unsafe { *(sptr::from_exposed_addr::<u8>(boot_params + E820_ENTRIES_OFFSET)) };
+	let e820_entries_address = unsafe { boot_params } + E820_TABLE_OFFSET;
+
+	(0..usize::from(linux_e820_entries)).map(move |index| {
+		//20: Size of one e820-Entry
+		let entry_address = e820_entries_address + index * 20;
+		let entry_start = unsafe { sptr::from_exposed_addr::<u64>(entry_address).read_unaligned() };
+		let entry_size =
+			unsafe { sptr::from_exposed_addr::<u64>(entry_address + 8).read_unaligned() };
+		let entry_type = unsafe { sptr::from_exposed_addr::<u32>(entry_address + 16).read() };
+		(entry_start, entry_size, entry_type)
+	})
+}
+
+/// Returns the usable RAM and the memory that is still needed for booting the kernel.
+pub(super) fn kaslr_regions() -> Option<KaslrRegions> {
+	/// The e820 type of usable RAM.
+	const E820_RAM: u32 = 1;
+
+	let usable = e820_entries()
+		.filter(|(_, _, entry_type)| *entry_type == E820_RAM)
+		.map(|(entry_start, entry_size, _)| entry_start..entry_start + entry_size)
+		.collect();
+
+	// The boot parameters and the command line are read when booting the kernel.
+	let params = unsafe { boot_params } as u64;
+	let mut reserved = Vec::new();
+	reserved.push(params..params + Size4KiB::SIZE);
+	if let Some(cmdline) = command_line() {
+		let start = cmdline.as_ptr().addr() as u64;
+		reserved.push(start..start + cmdline.len() as u64);
+	}
+
+	Some(KaslrRegions { usable, reserved })
+}
+
+pub fn find_kernel() -> Result<&'static [u8], LoaderError> {
 	use core::cmp;
 
 	paging::clean_up();
 
-	// Identity-map the Multiboot information.
-	unsafe {
-		assert!(boot_params > 0, "Could not find boot_params");
-		info!("Found boot_params at 0x{:x}", boot_params);
+	// Identity-map the boot parameters.
+	if unsafe { boot_params } == 0 {
+		return Err(LoaderError::BootProtocol {
+			protocol: PROTOCOL,
+			addr: None,
+			expected: "boot_params",
+		});
 	}
+	info!("Found boot_params at 0x{:x}", unsafe { boot_params });
 	let page_address = unsafe { boot_params }.align_down(Size4KiB::SIZE as usize);
 	paging::map::<Size4KiB>(page_address, page_address, 1, PageTableFlags::empty());
 
@@ -99,8 +195,15 @@ pub fn find_kernel() -> &'static [u8] {
 	info!("Intialize PhysAlloc with {:#x}", free_memory_address);
 	// Memory after the highest end address is unused and available for the physical memory manager.
 	PhysAlloc::init(free_memory_address);
-
-	assert!(ramdisk_address > 0);
+	super::init_heap();
+
+	if ramdisk_address == 0 || ramdisk_size == 0 {
+		return Err(LoaderError::BootProtocol {
+			protocol: PROTOCOL,
+			addr: Some(unsafe { boot_params } as u64),
+			expected: "the kernel as initrd in boot_params",
+		});
+	}
 	info!("Found an ELF module at {:#x}", elf_start);
 	let page_address = elf_start.align_down(Size4KiB::SIZE as usize);
 	let counter =
@@ -115,10 +218,17 @@ pub fn find_kernel() -> &'static [u8] {
 		paging::map::<Size2MiB>(address, address, counter, PageTableFlags::empty());
 	}
 
-	unsafe { slice::from_raw_parts(sptr::from_exposed_addr(elf_start), elf_len) }
+	Ok(unsafe { slice::from_raw_parts(sptr::from_exposed_addr(elf_start), elf_len) })
+}
+
+/// Returns the detached signature of the kernel.
+///
+/// Firecracker only provides a single initrd, so the signature has to be appended to the kernel.
+pub fn detached_signature() -> Option<&'static [u8]> {
+	None
 }
 
-pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
+pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> Result<Infallible, LoaderError> {
 	let LoadedKernel {
 		load_info,
 		entry_point,
@@ -127,39 +237,7 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 	// determine boot stack address
 	let new_stack = (ptr::addr_of!(loader_end).addr() + 0x1000).align_up(Size4KiB::SIZE as usize);
 
-	let cmdline_ptr = unsafe {
-		*(sptr::from_exposed_addr(boot_params + LINUX_SETUP_HEADER_OFFSET + CMD_LINE_PTR_OFFSET))
-	};
-	let cmdline_size: u32 = unsafe {
-		*(sptr::from_exposed_addr(boot_params + LINUX_SETUP_HEADER_OFFSET + CMD_LINE_SIZE_OFFSET))
-	};
-
-	let command_line = if cmdline_size > 0 {
-		// Identity-map the command line.
-		let page_address = (cmdline_ptr as usize).align_down(Size4KiB::SIZE as usize);
-		paging::map::<Size4KiB>(page_address, page_address, 1, PageTableFlags::empty());
-
-		info!("Found command line at {:#x}", cmdline_ptr);
-		let slice = unsafe {
-			core::slice::from_raw_parts(
-				sptr::from_exposed_addr(cmdline_ptr),
-				cmdline_size.try_into().unwrap(),
-			)
-		};
-
-		let s = core::str::from_utf8(slice)
-			.unwrap()
-			.strip_suffix('\0')
-			.unwrap();
-
-		if s.is_empty() {
-			None
-		} else {
-			Some(s)
-		}
-	} else {
-		None
-	};
+	let command_line = cmdline::edited().or_else(command_line);
 
 	// map stack in the address space
 	paging::map::<Size4KiB>(
@@ -176,7 +254,7 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 		write_bytes(stack, 0, KERNEL_STACK_SIZE.try_into().unwrap());
 	}
 
-	let mut fdt = Fdt::new("firecracker").unwrap();
+	let mut fdt = Fdt::new("firecracker")?;
 
 	// Load the boot_param memory-map information
 	let linux_e820_entries =
@@ -187,18 +265,9 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 	let mut start_address: usize = 0;
 	let mut end_address: usize = 0;
 
-	let e820_entries_address = unsafe { boot_params } + E820_TABLE_OFFSET;
-
-	for index in 0..linux_e820_entries {
+	for (index, (entry_start, entry_size, entry_type)) in e820_entries().enumerate() {
 		found_entry = true;
 
-		//20: Size of one e820-Entry
-		let entry_address = e820_entries_address + (index as usize) * 20;
-		let entry_start = unsafe { sptr::from_exposed_addr::<u64>(entry_address).read_unaligned() };
-		let entry_size =
-			unsafe { sptr::from_exposed_addr::<u64>(entry_address + 8).read_unaligned() };
-		let entry_type: u32 = unsafe { sptr::from_exposed_addr::<u32>(entry_address + 16).read() };
-
 		info!(
 			"e820-Entry with index {}: Address 0x{:x}, Size 0x{:x}, Type 0x{:x}",
 			index, entry_start, entry_size, entry_type
@@ -206,7 +275,7 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 
 		let entry_end = entry_start + entry_size;
 
-		fdt = fdt.memory(entry_start..entry_end).unwrap();
+		fdt = fdt.memory(entry_start..entry_end)?;
 
 		if start_address == 0 {
 			start_address = entry_start as usize;
@@ -218,7 +287,12 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 	}
 
 	// Identity-map the start of RAM
-	assert!(found_entry, "Could not find any free RAM areas!");
+	if !found_entry {
+		return Err(LoaderError::Memory {
+			protocol: PROTOCOL,
+			expected: "e820 entries in boot_params",
+		});
+	}
 
 	info!(
 		"Found available RAM: [0x{:x} - 0x{:x}]",
@@ -226,18 +300,32 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 	);
 
 	if let Some(command_line) = command_line {
-		fdt = fdt.bootargs(command_line).unwrap();
+		fdt = fdt
+			.bootargs(command_line)?
+			.env(cmdline::env(command_line))?;
+
+		for device in virtio_mmio::devices(command_line) {
+			fdt = fdt.virtio_mmio(device)?;
+		}
 	}
 
-	let fdt = fdt.finish().unwrap();
+	let acpi_rsdp_addr = unsafe {
+		sptr::from_exposed_addr::<u64>(boot_params + ACPI_RSDP_ADDR_OFFSET).read_unaligned()
+	};
+	let rsdp = if acpi_rsdp_addr != 0 {
+		acpi::check_rsdp(acpi_rsdp_addr).or_else(acpi::search_rsdp)
+	} else {
+		acpi::search_rsdp()
+	};
+
+	let fdt = super::describe_platform(fdt, rsdp)?.finish()?.leak();
 
-	let device_tree =
-		DeviceTreeAddress::new(u64::try_from(fdt.leak().as_ptr().expose_addr()).unwrap());
+	let device_tree = DeviceTreeAddress::new(u64::try_from(fdt.as_ptr().expose_addr()).unwrap());
 
 	let boot_info = BootInfo {
 		hardware_info: HardwareInfo {
 			phys_addr_range: start_address as u64..end_address as u64,
-			serial_port_base: SerialPortBase::new(SERIAL_IO_PORT),
+			serial_port_base: SerialPortBase::new(console::serial().port),
 			device_tree,
 		},
 		load_info,
@@ -249,6 +337,8 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 
 	let entry = sptr::from_exposed_addr(entry_point.try_into().unwrap());
 	let raw_boot_info = boot_info.write();
+	boot_time::finish(Some(fdt));
+	measured_boot::measure(Measurement::Fdt, fdt);
 
 	unsafe { super::enter_kernel(stack, entry, raw_boot_info) }
 }
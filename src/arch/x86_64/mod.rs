@@ -8,17 +8,127 @@ cfg_if::cfg_if! {
 	}
 }
 
+mod acpi;
+#[cfg(target_os = "none")]
+mod bios;
 mod console;
+mod exception;
+#[cfg(not(feature = "fc"))]
+mod framebuffer;
+#[cfg(target_os = "none")]
+mod mptable;
 #[cfg(target_os = "none")]
 mod paging;
 #[cfg(target_os = "none")]
 mod physicalmem;
+mod random;
+mod shutdown;
+#[cfg(not(feature = "fc"))]
+mod text;
+mod tsc;
+#[cfg(all(target_os = "none", not(feature = "fc")))]
+mod vga;
+#[cfg(target_os = "none")]
+mod virtio_mmio;
 
-pub use console::Console;
+pub use console::{configure, serial, Console};
+pub use exception::install_exception_handlers;
+#[cfg(target_os = "uefi")]
+pub use framebuffer::Framebuffer;
+pub use random::random_u64;
+pub use shutdown::{debug_exit, halt, poweroff, reboot};
+use vm_fdt::FdtWriterResult;
+
+use crate::fdt::Fdt;
 
 #[cfg(target_os = "none")]
 const KERNEL_STACK_SIZE: u64 = 32_768;
-pub const SERIAL_IO_PORT: u16 = 0x3F8;
+
+/// The size of the heap, which mainly holds the devicetree.
+///
+/// The devicetree grows with the number of CPUs, and the bump allocator never reuses the buffers that it outgrows.
+#[cfg(target_os = "none")]
+const HEAP_SIZE: usize = 0x10_0000;
+
+/// Memory regions for choosing a random kernel address.
+#[cfg(target_os = "none")]
+struct KaslrRegions {
+	/// The usable RAM.
+	usable: alloc::vec::Vec<core::ops::Range<u64>>,
+	/// Memory that is still needed for booting the kernel.
+	reserved: alloc::vec::Vec<core::ops::Range<u64>>,
+}
+
+/// Returns the current value of the TSC.
+pub fn timestamp() -> u64 {
+	tsc::rdtsc()
+}
+
+/// Returns the TSC frequency in Hz if it is known.
+pub fn timestamp_frequency() -> Option<u64> {
+	tsc::cached_frequency()
+}
+
+/// Returns the TSC frequency in Hz, determining it without the ACPI tables if it is not known yet.
+pub fn early_timestamp_frequency() -> Option<u64> {
+	tsc::cached_frequency().or_else(|| tsc::frequency(None))
+}
+
+/// Describes the platform in `fdt` using the ACPI tables referenced by `rsdp`.
+///
+/// On BIOS systems without ACPI, the MP tables are used instead.
+pub fn describe_platform(mut fdt: Fdt<'_>, rsdp: Option<u64>) -> FdtWriterResult<Fdt<'_>> {
+	if let Some(rsdp) = rsdp {
+		fdt = fdt.rsdp(rsdp)?;
+	}
+
+	let cpus = rsdp.and_then(acpi::cpus);
+	#[cfg(target_os = "none")]
+	let cpus = cpus.or_else(mptable::cpus);
+	if let Some(cpus) = cpus {
+		fdt = fdt.cpus(cpus)?;
+	}
+
+	if let Some(seed) = crate::entropy::seed() {
+		fdt = fdt.rng_seed(seed)?;
+	}
+
+	if let Some(frequency) = tsc::frequency(rsdp) {
+		fdt = fdt.timebase_frequency(frequency)?;
+	}
+
+	let serial = console::serial();
+	fdt = fdt.serial_port(serial.port, serial.irq(), serial.baud_rate)?;
+
+	if let Some(rsdp) = rsdp {
+		if let Some(hpet) = acpi::hpet_address(rsdp) {
+			fdt = fdt.hpet(hpet)?;
+		}
+
+		for io_apic in acpi::io_apics(rsdp).into_iter().flatten() {
+			fdt = fdt.io_apic(io_apic)?;
+		}
+	}
+
+	Ok(fdt)
+}
+
+/// Allocates the heap from [`PhysAlloc`](physicalmem::PhysAlloc) and switches the global allocator to it.
+///
+/// This must be called right after initializing [`PhysAlloc`](physicalmem::PhysAlloc).
+#[cfg(target_os = "none")]
+fn init_heap() {
+	use core::slice;
+
+	use x86_64::structures::paging::PageTableFlags;
+
+	use self::physicalmem::PhysAlloc;
+
+	let start = PhysAlloc::allocate(HEAP_SIZE);
+	paging::map_identity(start, start + HEAP_SIZE, PageTableFlags::WRITABLE);
+	let heap = unsafe { slice::from_raw_parts_mut(sptr::from_exposed_addr_mut(start), HEAP_SIZE) };
+	crate::os::init_heap(heap);
+}
 
 #[cfg(target_os = "none")]
 unsafe fn map_memory(address: usize, memory_size: usize) -> usize {
@@ -44,6 +154,29 @@ pub unsafe fn get_memory(memory_size: u64) -> u64 {
 	unsafe { map_memory(address, memory_size as usize) as u64 }
 }
 
+/// Returns memory at a random address for a relocatable kernel.
+#[cfg(target_os = "none")]
+pub unsafe fn get_random_memory(memory_size: u64) -> Option<u64> {
+	use align_address::Align;
+	use x86_64::structures::paging::{PageSize, Size2MiB};
+
+	use self::physicalmem::PhysAlloc;
+
+	let KaslrRegions { usable, reserved } = kaslr_regions()?;
+	// Memory below the physical memory allocator is used by the loader and the boot modules.
+	let allocated = 0..PhysAlloc::next() as u64;
+	let address = crate::kaslr::choose(
+		|| usable.iter().cloned(),
+		|| reserved.iter().cloned().chain([allocated.clone()]),
+		memory_size,
+		crate::entropy::kaslr_seed,
+	)?;
+
+	let address = address as usize;
+	PhysAlloc::reserve(address..address + (memory_size as usize).align_up(Size2MiB::SIZE as usize));
+	unsafe { Some(map_memory(address, memory_size as usize) as u64) }
+}
+
 pub unsafe fn enter_kernel(
 	stack: *mut u8,
 	entry: *const (),
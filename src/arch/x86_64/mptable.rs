@@ -0,0 +1,112 @@
+//! Minimal parsing of Intel MultiProcessor Specification tables.
+//!
+//! These are used by firmware without ACPI support, such as QEMU microvm with `acpi=off` and older Firecracker versions.
+//! See [MultiProcessor Specification Version 1.4](https://web.archive.org/web/20121002210153/http://download.intel.com/design/archives/processors/pro/docs/24201606.pdf) for details.
+
+use alloc::vec::Vec;
+use core::slice;
+
+use log::info;
+use x86_64::structures::paging::PageTableFlags;
+
+use super::acpi::is_checksum_valid;
+use super::{bios, paging};
+use crate::fdt::Cpu;
+
+/// Size of the MP floating pointer structure.
+const FLOATING_POINTER_LEN: usize = 16;
+
+/// Size of the MP configuration table header.
+const CONFIGURATION_TABLE_HEADER_LEN: usize = 44;
+
+/// Returns the address of the MP configuration table from a valid MP floating pointer structure at `addr`.
+fn floating_pointer(addr: usize) -> Option<usize> {
+	let bytes =
+		unsafe { slice::from_raw_parts(sptr::from_exposed_addr(addr), FLOATING_POINTER_LEN) };
+	if &bytes[..4] != b"_MP_" || bytes[8] != 1 || !is_checksum_valid(bytes) {
+		return None;
+	}
+
+	let configuration_table = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+	// A non-zero first feature byte denotes a default configuration without configuration table.
+	(configuration_table != 0 && bytes[11] == 0).then_some(configuration_table as usize)
+}
+
+/// Returns the validated MP configuration table at `addr` without extended entries.
+fn configuration_table(addr: usize) -> Option<&'static [u8]> {
+	paging::map_identity(
+		addr,
+		addr + CONFIGURATION_TABLE_HEADER_LEN,
+		PageTableFlags::empty(),
+	);
+	let header = unsafe {
+		slice::from_raw_parts(
+			sptr::from_exposed_addr(addr),
+			CONFIGURATION_TABLE_HEADER_LEN,
+		)
+	};
+	if &header[..4] != b"PCMP" {
+		return None;
+	}
+
+	let len = usize::from(u16::from_le_bytes(header[4..6].try_into().unwrap()));
+	if len < CONFIGURATION_TABLE_HEADER_LEN {
+		return None;
+	}
+
+	paging::map_identity(addr, addr + len, PageTableFlags::empty());
+	let table = unsafe { slice::from_raw_parts(sptr::from_exposed_addr(addr), len) };
+	is_checksum_valid(table).then_some(table)
+}
+
+/// Searches the MP floating pointer structure and returns the MP configuration table.
+fn find_configuration_table() -> Option<&'static [u8]> {
+	let low_area = bios::ebda().or_else(bios::base_memory_end);
+	let rom_area = 0xF0000..bios::BIOS_AREA.end;
+
+	let addr = low_area
+		.into_iter()
+		.chain([rom_area])
+		.find_map(|area| bios::search(area, 16, FLOATING_POINTER_LEN, floating_pointer))?;
+
+	info!("Found MP configuration table at {addr:#x}");
+	configuration_table(addr)
+}
+
+/// Returns the CPUs described by the processor entries of the MP configuration table.
+pub fn cpus() -> Option<Vec<Cpu>> {
+	const PROCESSOR: u8 = 0;
+	const PROCESSOR_ENTRY_LEN: usize = 20;
+	const OTHER_ENTRY_LEN: usize = 8;
+	const ENABLED: u8 = 1 << 0;
+
+	let table = find_configuration_table()?;
+	let entry_count = u16::from_le_bytes(table[34..36].try_into().unwrap());
+
+	let mut entries = &table[CONFIGURATION_TABLE_HEADER_LEN..];
+	let mut cpus = Vec::new();
+	for _ in 0..entry_count {
+		let Some(&ty) = entries.first() else {
+			break;
+		};
+		let len = if ty == PROCESSOR {
+			PROCESSOR_ENTRY_LEN
+		} else {
+			OTHER_ENTRY_LEN
+		};
+		let Some((entry, rest)) = entries.split_at_checked(len) else {
+			break;
+		};
+		entries = rest;
+
+		if ty == PROCESSOR {
+			cpus.push(Cpu {
+				id: u32::from(entry[1]),
+				enabled: entry[3] & ENABLED != 0,
+			});
+		}
+	}
+
+	info!("Found {} CPU(s) in the MP configuration table", cpus.len());
+	Some(cpus)
+}
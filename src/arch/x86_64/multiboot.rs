@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+use core::convert::Infallible;
 use core::ptr::write_bytes;
 use core::{mem, ptr, slice};
 
@@ -7,16 +9,22 @@ use hermit_entry::boot_info::{
 };
 use hermit_entry::elf::LoadedKernel;
 use log::info;
-use multiboot::information::{MemoryManagement, Multiboot, PAddr};
+use multiboot::information::{
+	ColorInfoType, FramebufferTable, MemoryManagement, MemoryType, Module, Multiboot, PAddr,
+};
 use sptr::Strict;
-use vm_fdt::FdtWriterResult;
 use x86_64::structures::paging::{PageSize, PageTableFlags, Size2MiB, Size4KiB};
 
-use super::paging;
+use super::framebuffer::Framebuffer;
 use super::physicalmem::PhysAlloc;
-use crate::arch::x86_64::{KERNEL_STACK_SIZE, SERIAL_IO_PORT};
+use super::{acpi, paging, virtio_mmio, KaslrRegions};
+use crate::arch::x86_64::{console, KERNEL_STACK_SIZE};
+use crate::error::LoaderError;
 use crate::fdt::Fdt;
-use crate::BootInfoExt;
+use crate::measured_boot::{self, Measurement};
+use crate::os::CONSOLE;
+use crate::slot::{self, Booted, Slot};
+use crate::{boot_time, cmdline, signature, BootInfoExt};
 
 extern "C" {
 	static mut loader_end: u8;
@@ -28,11 +36,18 @@ mod entry {
 	core::arch::global_asm!(include_str!("entry.s"));
 }
 
+const PROTOCOL: &str = "Multiboot";
+
 struct Mem;
 
 impl MemoryManagement for Mem {
 	unsafe fn paddr_to_slice<'a>(&self, p: PAddr, sz: usize) -> Option<&'static [u8]> {
-		let ptr = sptr::from_exposed_addr(p as usize);
+		let p = usize::try_from(p).ok()?;
+		if !paging::try_map_identity(p, p + sz, PageTableFlags::empty()) {
+			return None;
+		}
+
+		let ptr = sptr::from_exposed_addr(p);
 		unsafe { Some(slice::from_raw_parts(ptr, sz)) }
 	}
 
@@ -48,104 +63,239 @@ impl MemoryManagement for Mem {
 	}
 }
 
+/// Returns the command line from the Multiboot information.
+///
+/// This may be called before the physical memory allocator is initialized.
+pub fn command_line() -> Option<&'static str> {
+	if unsafe { mb_info } == 0 {
+		return None;
+	}
+
+	let mut mem = Mem;
+	let multiboot = unsafe { Multiboot::from_ptr(mb_info as u64, &mut mem)? };
+	multiboot.command_line()
+}
+
+/// Returns the Multiboot information.
+fn multiboot(mem: &mut Mem) -> Result<Multiboot<'static, '_>, LoaderError> {
+	let addr = unsafe { mb_info };
+	let err = LoaderError::BootProtocol {
+		protocol: PROTOCOL,
+		addr: (addr != 0).then_some(addr as u64),
+		expected: "the Multiboot information",
+	};
+
+	if addr == 0 {
+		return Err(err);
+	}
+
+	unsafe { Multiboot::from_ptr(addr as u64, mem) }.ok_or(err)
+}
+
+/// Returns the usable RAM and the memory that is still needed for booting the kernel.
+pub(super) fn kaslr_regions() -> Option<KaslrRegions> {
+	let mut mem = Mem;
+	let multiboot = multiboot(&mut mem).ok()?;
+
+	let usable = multiboot
+		.memory_regions()?
+		.filter(|region| region.memory_type() == MemoryType::Available)
+		.map(|region| region.base_address()..region.base_address() + region.length())
+		.collect();
+
+	// The Multiboot information and the command line are read when booting the kernel.
+	// The kernel stack may be placed behind them.
+	let len = Size4KiB::SIZE + KERNEL_STACK_SIZE;
+	let info = unsafe { mb_info } as u64;
+	let mut reserved = Vec::new();
+	reserved.push(info..info + len);
+	if let Some(cmdline) = multiboot.command_line() {
+		let cmdline = cmdline.as_ptr().addr() as u64;
+		reserved.push(cmdline..cmdline + len);
+	}
+
+	Some(KaslrRegions { usable, reserved })
+}
+
 pub struct DeviceTree;
 
 impl DeviceTree {
-	pub fn create() -> FdtWriterResult<&'static [u8]> {
+	pub fn create() -> Result<&'static mut [u8], LoaderError> {
 		let mut mem = Mem;
-		let multiboot = unsafe { Multiboot::from_ptr(mb_info as u64, &mut mem).unwrap() };
+		let multiboot = multiboot(&mut mem)?;
 
-		let memory_regions = multiboot
-			.memory_regions()
-			.expect("Could not find a memory map in the Multiboot information");
+		let memory_regions = multiboot.memory_regions().ok_or(LoaderError::Memory {
+			protocol: PROTOCOL,
+			expected: "a memory map in the Multiboot information",
+		})?;
 
 		let mut fdt = Fdt::new("multiboot")?.memory_regions(memory_regions)?;
 
-		if let Some(cmdline) = multiboot.command_line() {
-			fdt = fdt.bootargs(cmdline)?;
+		if let Some(cmdline) = cmdline::edited().or_else(|| multiboot.command_line()) {
+			fdt = fdt.bootargs(cmdline)?.env(cmdline::env(cmdline))?;
+
+			for device in virtio_mmio::devices(cmdline) {
+				fdt = fdt.virtio_mmio(device)?;
+			}
 		}
 
-		let fdt = fdt.finish()?;
+		// Multiboot does not tell us about ACPI, so we have to search for it.
+		let rsdp = acpi::search_rsdp();
+		let fdt = super::describe_platform(fdt, rsdp)?.finish()?;
 
 		Ok(fdt.leak())
 	}
 }
 
-pub fn find_kernel() -> &'static [u8] {
+/// Returns the framebuffer if it is in direct RGB color mode.
+fn framebuffer(table: &FramebufferTable) -> Option<Framebuffer> {
+	let ColorInfoType::Rgb(rgb) = table.color_info()? else {
+		return None;
+	};
+
+	let mask = |position: u8, size: u8| (((1u64 << size.min(32)) - 1) << position.min(32)) as u32;
+	let framebuffer = Framebuffer {
+		addr: usize::try_from(table.addr).ok()?,
+		pitch: table.pitch as usize,
+		width: table.width as usize,
+		height: table.height as usize,
+		bytes_per_pixel: usize::from(table.bpp.div_ceil(8)),
+		foreground: mask(rgb.red_field_position, rgb.red_mask_size)
+			| mask(rgb.green_field_position, rgb.green_mask_size)
+			| mask(rgb.blue_field_position, rgb.blue_mask_size),
+	};
+
+	framebuffer.is_valid().then_some(framebuffer)
+}
+
+pub fn find_kernel() -> Result<&'static [u8], LoaderError> {
 	use core::cmp;
 
 	paging::clean_up();
-	// Identity-map the Multiboot information.
-	unsafe {
-		assert!(mb_info > 0, "Could not find Multiboot information");
-		info!("Found Multiboot information at {:#x}", mb_info);
-		paging::map::<Size4KiB>(mb_info, mb_info, 1, PageTableFlags::empty())
-	}
 
 	let mut mem = Mem;
 	// Load the Multiboot information and identity-map the modules information.
-	let multiboot = unsafe { Multiboot::from_ptr(mb_info as u64, &mut mem).unwrap() };
+	let multiboot = multiboot(&mut mem)?;
+	info!("Found Multiboot information at {:#x}", unsafe { mb_info });
 
 	// Iterate through all modules.
 	// Collect the start address of the first module and the highest end address of all modules.
-	let mut module_iter = multiboot
-		.modules()
-		.expect("Could not find a memory map in the Multiboot information");
-
-	let first_module = module_iter
-		.next()
-		.expect("Could not find a single module in the Multiboot information");
+	let no_kernel = || LoaderError::BootProtocol {
+		protocol: PROTOCOL,
+		addr: Some(unsafe { mb_info } as u64),
+		expected: "the kernel as first module in the Multiboot information",
+	};
+	let slot = slot();
+	let (_, kernel_module) = kernel_module(&multiboot, slot).ok_or_else(no_kernel)?;
+	if let Some(slot) = slot {
+		info!("Booting slot {slot}");
+		slot::set_booted(Booted {
+			slot,
+			state_variable: None,
+		});
+	}
 	info!(
 		"Found an ELF module at [{:#x} - {:#x}]",
-		first_module.start, first_module.end
+		kernel_module.start, kernel_module.end
 	);
-	let elf_start = first_module.start as usize;
-	let elf_len = (first_module.end - first_module.start) as usize;
+	let elf_start = kernel_module.start as usize;
+	let elf_len = (kernel_module.end - kernel_module.start) as usize;
 	info!("Module length: {:#x}", elf_len);
 
-	// Find the maximum end address from the remaining modules
-	let mut end_address = first_module.end;
-	for m in module_iter {
-		end_address = cmp::max(end_address, m.end);
-	}
+	// Find the maximum end address from all modules
+	let end_address = multiboot
+		.modules()
+		.ok_or_else(no_kernel)?
+		.map(|module| module.end)
+		.fold(kernel_module.end, cmp::max);
 
 	let modules_mapping_end = end_address.align_up(Size2MiB::SIZE) as usize;
 	// TODO: Workaround for https://github.com/hermitcore/loader/issues/96
 	let free_memory_address = cmp::max(modules_mapping_end, 0x800000);
 	// Memory after the highest end address is unused and available for the physical memory manager.
 	PhysAlloc::init(free_memory_address);
+	super::init_heap();
+
+	if let Some(framebuffer) = multiboot.framebuffer_table().and_then(framebuffer) {
+		info!("Found a framebuffer: {framebuffer:x?}");
+		paging::map_identity(
+			framebuffer.addr,
+			framebuffer.addr + framebuffer.size(),
+			PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE,
+		);
+		CONSOLE.lock().get().set_framebuffer(framebuffer);
+	}
 
-	// Identity-map the ELF header of the first module and until the 2 MiB
+	// Identity-map the ELF header of the kernel module and until the 2 MiB
 	// mapping starts. We cannot start the 2 MiB mapping right from
-	// `first_module.end` because when it is aligned down, the
+	// `kernel_module.end` because when it is aligned down, the
 	// resulting mapping range may overlap with the 4 KiB mapping.
-	let first_module_mapping_end = first_module.start.align_up(Size2MiB::SIZE) as usize;
+	let kernel_module_mapping_end = kernel_module.start.align_up(Size2MiB::SIZE) as usize;
 	paging::map_range::<Size4KiB>(
-		first_module.start as usize,
-		first_module.start as usize,
-		first_module_mapping_end,
+		kernel_module.start as usize,
+		kernel_module.start as usize,
+		kernel_module_mapping_end,
 		PageTableFlags::empty(),
 	);
 
 	// map also the rest of the modules
 	paging::map_range::<Size2MiB>(
-		first_module_mapping_end,
-		first_module_mapping_end,
+		kernel_module_mapping_end,
+		kernel_module_mapping_end,
 		modules_mapping_end,
 		PageTableFlags::empty(),
 	);
 
-	unsafe { slice::from_raw_parts(sptr::from_exposed_addr(elf_start), elf_len) }
+	Ok(unsafe { slice::from_raw_parts(sptr::from_exposed_addr(elf_start), elf_len) })
+}
+
+/// Returns the slot selected with `loader.slot`.
+fn slot() -> Option<Slot> {
+	cmdline::edited()
+		.or_else(command_line)
+		.and_then(slot::from_cmdline)
+}
+
+/// Returns the index and the module of the kernel for `slot`.
+///
+/// With `loader.slot`, the slots are the first two modules that are not detached signatures.
+fn kernel_module<'a>(
+	multiboot: &'a Multiboot<'a, '_>,
+	slot: Option<Slot>,
+) -> Option<(usize, Module<'a>)> {
+	multiboot
+		.modules()?
+		.enumerate()
+		.filter(|(_, module)| {
+			slot.is_none() || module.end - module.start != signature::TRAILER_SIZE as u64
+		})
+		.nth(slot.map_or(0, |slot| slot.index().into()))
+}
+
+/// Returns the detached signature of the kernel, which is the module directly behind the kernel.
+pub fn detached_signature() -> Option<&'static [u8]> {
+	let mut mem = Mem;
+	let multiboot = multiboot(&mut mem).ok()?;
+	let (index, _) = kernel_module(&multiboot, slot())?;
+	let signature = multiboot
+		.modules()?
+		.nth(index + 1)
+		.filter(|module| module.end - module.start == signature::TRAILER_SIZE as u64)
+		.and_then(|module| unsafe { Mem.paddr_to_slice(module.start, signature::TRAILER_SIZE) })
+		.filter(|module| signature::has_trailer(module))?;
+
+	info!("Found a detached signature module");
+	Some(signature)
 }
 
-pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
+pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> Result<Infallible, LoaderError> {
 	let LoadedKernel {
 		load_info,
 		entry_point,
 	} = kernel_info;
 
 	let mut mem = Mem;
-	let multiboot = unsafe { Multiboot::from_ptr(mb_info as u64, &mut mem).unwrap() };
+	let multiboot = multiboot(&mut mem)?;
 
 	// determine boot stack address
 	let mut new_stack = ptr::addr_of!(loader_end)
@@ -181,14 +331,13 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 		write_bytes(stack, 0, KERNEL_STACK_SIZE.try_into().unwrap());
 	}
 
-	let device_tree = DeviceTree::create().expect("Unable to create devicetree!");
-	let device_tree =
-		DeviceTreeAddress::new(u64::try_from(device_tree.as_ptr().expose_addr()).unwrap());
+	let fdt = DeviceTree::create()?;
+	let device_tree = DeviceTreeAddress::new(u64::try_from(fdt.as_ptr().expose_addr()).unwrap());
 
 	let boot_info = BootInfo {
 		hardware_info: HardwareInfo {
 			phys_addr_range: 0..0,
-			serial_port_base: SerialPortBase::new(SERIAL_IO_PORT),
+			serial_port_base: SerialPortBase::new(console::serial().port),
 			device_tree,
 		},
 		load_info,
@@ -200,6 +349,8 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel) -> ! {
 
 	let entry = sptr::from_exposed_addr(entry_point.try_into().unwrap());
 	let raw_boot_info = boot_info.write();
+	boot_time::finish(Some(fdt));
+	measured_boot::measure(Measurement::Fdt, fdt);
 
 	unsafe { super::enter_kernel(stack, entry, raw_boot_info) }
 }
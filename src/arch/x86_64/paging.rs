@@ -1,9 +1,10 @@
 use core::fmt::Debug;
 
+use align_address::Align;
 use log::warn;
-use x86_64::structures::paging::mapper::CleanUp;
+use x86_64::structures::paging::mapper::{CleanUp, MapToError};
 use x86_64::structures::paging::{
-	Mapper, Page, PageSize, PageTableFlags, PhysFrame, RecursivePageTable,
+	Mapper, Page, PageSize, PageTableFlags, PhysFrame, RecursivePageTable, Size4KiB, Translate,
 };
 
 use super::physicalmem::PhysAlloc;
@@ -40,12 +41,7 @@ where
 	let mut table = unsafe { recursive_page_table() };
 
 	for (page, frame) in pages.zip(frames) {
-		unsafe {
-			table
-				.map_to(page, frame, flags, &mut PhysAlloc)
-				.unwrap()
-				.flush();
-		}
+		unsafe { map_to(&mut table, page, frame, flags) }
 	}
 }
 
@@ -74,15 +70,91 @@ pub fn map_range<S>(
 	let page_range = core::iter::successors(Some(first_page), |page| Some(*page + 1u64));
 	let frame_range = PhysFrame::<S>::range(first_frame, last_frame);
 	for (page, frame) in core::iter::zip(page_range, frame_range) {
-		unsafe {
-			table
-				.map_to(page, frame, flags, &mut PhysAlloc)
-				.unwrap()
-				.flush();
+		unsafe { map_to(&mut table, page, frame, flags) }
+	}
+}
+
+/// Maps `page` to `frame`, tolerating an existing identical mapping.
+unsafe fn map_to<S>(
+	table: &mut RecursivePageTable<'static>,
+	page: Page<S>,
+	frame: PhysFrame<S>,
+	flags: PageTableFlags,
+) where
+	S: PageSize + Debug,
+	RecursivePageTable<'static>: Mapper<S>,
+{
+	match unsafe { table.map_to(page, frame, flags, &mut PhysAlloc) } {
+		Ok(flush) => flush.flush(),
+		Err(MapToError::PageAlreadyMapped(mapped)) if mapped == frame => {}
+		Err(err) => panic!("Could not map {page:?} to {frame:?}: {err:?}"),
+	}
+}
+
+/// End of the memory covered by the bootstrap page tables.
+///
+/// Below, pages can be mapped without allocating frames for page tables.
+const BOOTSTRAP_MAPPING_END: usize = 0x400000;
+
+/// Identity-maps `start..end` like [`map_identity`] if this is possible without [`PhysAlloc`].
+///
+/// This is used for reading boot information before [`PhysAlloc`] is initialized.
+/// Returns whether the memory is mapped.
+pub fn try_map_identity(start: usize, end: usize, flags: PageTableFlags) -> bool {
+	if !PhysAlloc::is_initialized() && end > BOOTSTRAP_MAPPING_END {
+		return false;
+	}
+
+	map_identity(start, end, flags);
+	true
+}
+
+/// Identity-maps all 4 KiB pages in `start..end` that are not mapped yet.
+pub fn map_identity(start: usize, end: usize, flags: PageTableFlags) {
+	let page_size = Size4KiB::SIZE as usize;
+	let end = end.align_up(page_size);
+	let mut addr = start.align_down(page_size);
+
+	while addr < end {
+		if is_mapped(addr) {
+			addr += page_size;
+			continue;
+		}
+
+		let run_start = addr;
+		while addr < end && !is_mapped(addr) {
+			addr += page_size;
 		}
+		map::<Size4KiB>(run_start, run_start, (addr - run_start) / page_size, flags);
 	}
 }
 
+pub fn unmap<S>(virtual_address: usize, count: usize)
+where
+	S: PageSize + Debug,
+	RecursivePageTable<'static>: Mapper<S>,
+{
+	let pages = {
+		let start = Page::<S>::containing_address(x86_64::VirtAddr::new(virtual_address as u64));
+		let end = start + count as u64;
+		Page::range(start, end)
+	};
+
+	let mut table = unsafe { recursive_page_table() };
+
+	for page in pages {
+		table.unmap(page).unwrap().1.flush();
+	}
+}
+
+pub fn is_mapped(virtual_address: usize) -> bool {
+	let table = unsafe { recursive_page_table() };
+
+	table
+		.translate_addr(x86_64::VirtAddr::new(virtual_address as u64))
+		.is_some()
+}
+
 pub fn clean_up() {
 	let mut table = unsafe { recursive_page_table() };
 
@@ -1,4 +1,5 @@
 use core::num::NonZeroUsize;
+use core::ops::Range;
 
 use log::debug;
 use one_shot_mutex::OneShotMutex;
@@ -8,19 +9,30 @@ static PHYS_ALLOC: OneShotMutex<Option<PhysAllocInner>> = OneShotMutex::new(None
 
 struct PhysAllocInner {
 	next: NonZeroUsize,
+	/// Memory that must not be allocated, like a randomly placed kernel.
+	reserved: Option<Range<usize>>,
 }
 
 impl PhysAllocInner {
 	pub fn new(addr: NonZeroUsize) -> Self {
-		Self { next: addr }
+		Self {
+			next: addr,
+			reserved: None,
+		}
 	}
 
 	pub fn allocate(&mut self, size: usize) -> usize {
 		assert_ne!(size, 0);
 		assert_eq!(size % Size4KiB::SIZE as usize, 0);
 
-		let addr = self.next.get();
-		self.next = self.next.checked_add(size).unwrap();
+		let mut addr = self.next.get();
+		if let Some(reserved) = &self.reserved {
+			if addr < reserved.end && reserved.start < addr + size {
+				addr = reserved.end;
+			}
+		}
+
+		self.next = NonZeroUsize::new(addr).unwrap().checked_add(size).unwrap();
 		addr
 	}
 }
@@ -33,9 +45,26 @@ impl PhysAlloc {
 		phys_alloc.replace(PhysAllocInner::new(addr.try_into().unwrap()));
 	}
 
+	pub fn is_initialized() -> bool {
+		PHYS_ALLOC.lock().is_some()
+	}
+
 	pub fn allocate(size: usize) -> usize {
 		PHYS_ALLOC.lock().as_mut().unwrap().allocate(size)
 	}
+
+	/// Returns the address of the next allocation.
+	pub fn next() -> usize {
+		PHYS_ALLOC.lock().as_ref().unwrap().next.get()
+	}
+
+	/// Prevents allocations from `range`.
+	pub fn reserve(range: Range<usize>) {
+		let mut phys_alloc = PHYS_ALLOC.lock();
+		let phys_alloc = phys_alloc.as_mut().unwrap();
+		assert!(phys_alloc.reserved.is_none());
+		phys_alloc.reserved = Some(range);
+	}
 }
 
 unsafe impl<S: PageSize> FrameAllocator<S> for PhysAlloc {
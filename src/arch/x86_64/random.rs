@@ -0,0 +1,37 @@
+//! Random numbers from the processor.
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid_count;
+
+use x86_64::instructions::random::RdRand;
+
+/// The number of attempts recommended by Intel before giving up.
+const RETRIES: usize = 10;
+
+/// Returns a random number from `RDSEED` or `RDRAND`.
+pub fn random_u64() -> Option<u64> {
+	rdseed().or_else(|| RdRand::new()?.get_u64())
+}
+
+fn rdseed() -> Option<u64> {
+	// CPUID.(EAX=07H, ECX=0H):EBX.RDSEED[bit 18]
+	let cpuid = __cpuid_count(7, 0);
+	if cpuid.ebx & (1 << 18) == 0 {
+		return None;
+	}
+
+	(0..RETRIES).find_map(|_| {
+		let value: u64;
+		let success: u8;
+		unsafe {
+			asm!(
+				"rdseed {value}",
+				"setc {success}",
+				value = out(reg) value,
+				success = out(reg_byte) success,
+				options(nomem, nostack),
+			);
+		}
+		(success != 0).then_some(value)
+	})
+}
@@ -0,0 +1,69 @@
+//! Stopping the machine after fatal errors.
+
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::PortWriteOnly;
+use x86_64::instructions::tables::lidt;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+/// The I/O port of QEMU's `isa-debug-exit` device as passed by `xtask ci qemu`.
+const DEBUG_EXIT_PORT: u16 = 0xF4;
+
+/// The command port of the 8042 keyboard controller.
+const KBC_COMMAND_PORT: u16 = 0x64;
+
+/// Pulses the reset line of the processor.
+const KBC_RESET: u8 = 0xFE;
+
+pub fn halt() -> ! {
+	interrupts::disable();
+	loop {
+		x86_64::instructions::hlt();
+	}
+}
+
+pub fn reboot() -> ! {
+	unsafe {
+		PortWriteOnly::<u8>::new(KBC_COMMAND_PORT).write(KBC_RESET);
+	}
+
+	// Without a keyboard controller, reset the processor with a triple fault.
+	let idt = DescriptorTablePointer {
+		limit: 0,
+		base: VirtAddr::zero(),
+	};
+	unsafe {
+		lidt(&idt);
+	}
+	interrupts::int3();
+
+	halt()
+}
+
+/// Turns off the machine.
+///
+/// On UEFI, this uses the `ResetSystem` runtime service.
+/// Otherwise, the loader cannot turn off the machine without ACPI, so it halts.
+pub fn poweroff() -> ! {
+	#[cfg(target_os = "uefi")]
+	uefi::runtime::reset(
+		uefi::runtime::ResetType::SHUTDOWN,
+		uefi::Status::ABORTED,
+		None,
+	);
+
+	#[cfg(not(target_os = "uefi"))]
+	halt()
+}
+
+/// Exits QEMU with a failure status using `isa-debug-exit`.
+///
+/// `isa-debug-exit` exits QEMU with the status `(value << 1) | 1`.
+/// If the device is missing, this falls back to [`poweroff`].
+pub fn debug_exit() -> ! {
+	unsafe {
+		PortWriteOnly::<u32>::new(DEBUG_EXIT_PORT).write(0);
+	}
+
+	poweroff()
+}
@@ -0,0 +1,94 @@
+//! Text output on character grids like VGA text mode or a framebuffer.
+
+/// A grid of characters.
+pub trait Grid {
+	fn columns(&self) -> usize;
+
+	fn rows(&self) -> usize;
+
+	/// Draws `byte` at `column` and `row`.
+	fn put(&mut self, column: usize, row: usize, byte: u8);
+
+	/// Moves all rows up by one and clears the last row.
+	fn scroll(&mut self);
+}
+
+/// The state of ANSI escape sequence parsing.
+///
+/// Escape sequences, like those for colored log output, are skipped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+	Text,
+	Escape,
+	ControlSequence,
+}
+
+/// A terminal writing to a [`Grid`].
+pub struct TextConsole<G> {
+	grid: G,
+	column: usize,
+	row: usize,
+	state: State,
+}
+
+impl<G: Grid> TextConsole<G> {
+	pub fn new(mut grid: G) -> Self {
+		for row in 0..grid.rows() {
+			for column in 0..grid.columns() {
+				grid.put(column, row, b' ');
+			}
+		}
+
+		Self {
+			grid,
+			column: 0,
+			row: 0,
+			state: State::Text,
+		}
+	}
+
+	pub fn write_bytes(&mut self, bytes: &[u8]) {
+		for byte in bytes.iter().copied() {
+			self.write_byte(byte);
+		}
+	}
+
+	fn write_byte(&mut self, byte: u8) {
+		match (self.state, byte) {
+			(State::Text, 0x1B) => self.state = State::Escape,
+			(State::Escape, b'[') => self.state = State::ControlSequence,
+			(State::Escape, _) => self.state = State::Text,
+			(State::ControlSequence, 0x40..=0x7E) => self.state = State::Text,
+			(State::ControlSequence, _) => {}
+			(State::Text, b'\n') => self.new_line(),
+			(State::Text, b'\r') => self.column = 0,
+			(State::Text, b'\t') => {
+				for _ in 0..8 - self.column % 8 {
+					self.write_char(b' ');
+				}
+			}
+			(State::Text, 0x20..=0x7E) => self.write_char(byte),
+			// Skip UTF-8 continuation bytes so that each character is replaced only once.
+			(State::Text, 0x80..=0xBF) => {}
+			(State::Text, _) => self.write_char(b'?'),
+		}
+	}
+
+	fn write_char(&mut self, byte: u8) {
+		if self.column >= self.grid.columns() {
+			self.new_line();
+		}
+
+		self.grid.put(self.column, self.row, byte);
+		self.column += 1;
+	}
+
+	fn new_line(&mut self) {
+		self.column = 0;
+		if self.row + 1 < self.grid.rows() {
+			self.row += 1;
+		} else {
+			self.grid.scroll();
+		}
+	}
+}
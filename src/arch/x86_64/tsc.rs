@@ -0,0 +1,207 @@
+//! Determination of the time stamp counter (TSC) frequency.
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use log::info;
+
+/// Calibration period in milliseconds.
+const CALIBRATION_MS: u64 = 10;
+
+/// The maximum number of polls during calibration before giving up.
+#[cfg(target_os = "none")]
+const MAX_POLLS: u32 = 100_000_000;
+
+/// The TSC frequency determined by [`frequency`] or 0.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the TSC frequency in Hz.
+///
+/// `rsdp` is used for finding the HPET for calibration.
+pub fn frequency(rsdp: Option<u64>) -> Option<u64> {
+	let (frequency, source) = if let Some(frequency) = from_cpuid() {
+		(frequency, "CPUID")
+	} else if let Some(frequency) = from_hypervisor() {
+		(frequency, "hypervisor CPUID")
+	} else {
+		calibrate(rsdp)?
+	};
+
+	info!("TSC frequency: {} kHz (from {source})", frequency / 1000);
+	FREQUENCY.store(frequency, Ordering::Relaxed);
+	Some(frequency)
+}
+
+/// Returns the TSC frequency in Hz if it has already been determined by [`frequency`].
+pub fn cached_frequency() -> Option<u64> {
+	let frequency = FREQUENCY.load(Ordering::Relaxed);
+	(frequency != 0).then_some(frequency)
+}
+
+/// Reads the TSC frequency from the Time Stamp Counter and Nominal Core Crystal Clock Information Leaf (0x15).
+///
+/// If the leaf does not report the crystal clock frequency, it is derived from the processor base frequency
+/// from the Processor Frequency Information Leaf (0x16) like Linux does.
+fn from_cpuid() -> Option<u64> {
+	let max_leaf = __cpuid(0).eax;
+	if max_leaf < 0x15 {
+		return None;
+	}
+
+	let leaf = __cpuid(0x15);
+	let (denominator, numerator, crystal_hz) = (leaf.eax, leaf.ebx, leaf.ecx);
+	if denominator == 0 || numerator == 0 {
+		return None;
+	}
+
+	let crystal_hz = if crystal_hz != 0 {
+		u64::from(crystal_hz)
+	} else if max_leaf >= 0x16 {
+		let base_mhz = __cpuid(0x16).eax & 0xFFFF;
+		u64::from(base_mhz) * 1_000_000 * u64::from(denominator) / u64::from(numerator)
+	} else {
+		return None;
+	};
+
+	let frequency = crystal_hz * u64::from(numerator) / u64::from(denominator);
+	(frequency != 0).then_some(frequency)
+}
+
+/// Reads the TSC frequency from the timing information leaf (0x40000010) of KVM and VMware.
+fn from_hypervisor() -> Option<u64> {
+	const HYPERVISOR_PRESENT: u32 = 1 << 31;
+	const TIMING_LEAF: u32 = 0x4000_0010;
+
+	if __cpuid(1).ecx & HYPERVISOR_PRESENT == 0 {
+		return None;
+	}
+
+	if __cpuid(0x4000_0000).eax < TIMING_LEAF {
+		return None;
+	}
+
+	let tsc_khz = __cpuid(TIMING_LEAF).eax;
+	(tsc_khz != 0).then(|| u64::from(tsc_khz) * 1000)
+}
+
+pub fn rdtsc() -> u64 {
+	unsafe { _rdtsc() }
+}
+
+#[cfg(target_os = "uefi")]
+fn calibrate(_rsdp: Option<u64>) -> Option<(u64, &'static str)> {
+	let start = rdtsc();
+	uefi::boot::stall(CALIBRATION_MS as usize * 1000);
+	let end = rdtsc();
+
+	Some((
+		(end - start) * 1000 / CALIBRATION_MS,
+		"UEFI Stall calibration",
+	))
+}
+
+#[cfg(target_os = "none")]
+fn calibrate(rsdp: Option<u64>) -> Option<(u64, &'static str)> {
+	if let Some(frequency) = calibrate_pit() {
+		return Some((frequency, "PIT calibration"));
+	}
+
+	let hpet = rsdp.and_then(super::acpi::hpet_address)?;
+	calibrate_hpet(hpet).map(|frequency| (frequency, "HPET calibration"))
+}
+
+/// Calibrates the TSC against channel 2 of the programmable interval timer (PIT).
+///
+/// Adapted from Linux' `pit_calibrate_tsc`.
+#[cfg(target_os = "none")]
+fn calibrate_pit() -> Option<u64> {
+	use x86_64::instructions::port::Port;
+
+	const PIT_FREQUENCY: u64 = 1_193_182;
+	const LATCH: u64 = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+	/// The minimum number of polls until the PIT expires.
+	///
+	/// Fewer polls indicate a missing PIT.
+	const MIN_POLLS: u32 = 1000;
+
+	let mut speaker = Port::<u8>::new(0x61);
+	let mut command = Port::<u8>::new(0x43);
+	let mut channel2 = Port::<u8>::new(0x42);
+
+	unsafe {
+		// Enable the gate of channel 2 and disable the speaker.
+		let value = speaker.read();
+		speaker.write((value & !0x02) | 0x01);
+
+		// Channel 2, access mode lobyte/hibyte, mode 0 (interrupt on terminal count), binary.
+		command.write(0b1011_0000);
+		channel2.write((LATCH & 0xFF) as u8);
+		channel2.write((LATCH >> 8) as u8);
+	}
+
+	let start = rdtsc();
+	let mut polls = 0;
+	// Wait for the output of channel 2 to go high.
+	while unsafe { speaker.read() } & 0x20 == 0 {
+		polls += 1;
+		if polls > MAX_POLLS {
+			return None;
+		}
+	}
+	let end = rdtsc();
+
+	(polls >= MIN_POLLS).then(|| (end - start) * 1000 / CALIBRATION_MS)
+}
+
+/// Calibrates the TSC against the main counter of the high precision event timer (HPET).
+#[cfg(target_os = "none")]
+fn calibrate_hpet(hpet: u64) -> Option<u64> {
+	use x86_64::structures::paging::PageTableFlags;
+
+	const GENERAL_CAPABILITIES: usize = 0x000;
+	const GENERAL_CONFIGURATION: usize = 0x010;
+	const MAIN_COUNTER: usize = 0x0F0;
+	const ENABLE_CNF: u64 = 1 << 0;
+
+	let hpet = usize::try_from(hpet).ok()?;
+	super::paging::map_identity(
+		hpet,
+		hpet + 0x400,
+		PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE,
+	);
+
+	let register = |offset: usize| sptr::from_exposed_addr_mut::<u64>(hpet + offset);
+	let read = |offset: usize| unsafe { register(offset).read_volatile() };
+
+	let period_fs = read(GENERAL_CAPABILITIES) >> 32;
+	if period_fs == 0 || period_fs > 100_000_000 {
+		return None;
+	}
+
+	let configuration = read(GENERAL_CONFIGURATION);
+	if configuration & ENABLE_CNF == 0 {
+		unsafe {
+			register(GENERAL_CONFIGURATION).write_volatile(configuration | ENABLE_CNF);
+		}
+	}
+
+	let ticks = CALIBRATION_MS * 1_000_000_000_000 / period_fs;
+	let start_counter = read(MAIN_COUNTER);
+	let start = rdtsc();
+	let mut polls = 0u32;
+	let mut expired = true;
+	while read(MAIN_COUNTER).wrapping_sub(start_counter) < ticks {
+		polls += 1;
+		if polls > MAX_POLLS {
+			expired = false;
+			break;
+		}
+	}
+	let end = rdtsc();
+
+	unsafe {
+		register(GENERAL_CONFIGURATION).write_volatile(configuration);
+	}
+
+	expired.then(|| (end - start) * 1000 / CALIBRATION_MS)
+}
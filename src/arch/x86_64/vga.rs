@@ -0,0 +1,71 @@
+//! VGA text mode.
+
+use x86_64::structures::paging::PageTableFlags;
+
+use super::paging;
+use super::text::Grid;
+
+/// The VGA text buffer in 80x25 color text mode.
+pub struct VgaText {
+	buffer: *mut u16,
+}
+
+impl VgaText {
+	const ADDRESS: usize = 0xB8000;
+	/// The end of the memory of the color text modes.
+	const END: usize = 0xC0000;
+	const COLUMNS: usize = 80;
+	const ROWS: usize = 25;
+
+	/// Light gray on black.
+	const ATTRIBUTE: u16 = 0x07 << 8;
+
+	/// Identity-maps the text buffer and returns it, or `None` if it cannot be mapped yet.
+	pub fn new() -> Option<Self> {
+		if !paging::try_map_identity(Self::ADDRESS, Self::END, PageTableFlags::WRITABLE) {
+			return None;
+		}
+
+		Some(Self {
+			buffer: sptr::from_exposed_addr_mut(Self::ADDRESS),
+		})
+	}
+
+	fn write(&mut self, index: usize, value: u16) {
+		unsafe { self.buffer.add(index).write_volatile(value) }
+	}
+
+	fn read(&self, index: usize) -> u16 {
+		unsafe { self.buffer.add(index).read_volatile() }
+	}
+}
+
+impl Grid for VgaText {
+	fn columns(&self) -> usize {
+		Self::COLUMNS
+	}
+
+	fn rows(&self) -> usize {
+		Self::ROWS
+	}
+
+	fn put(&mut self, column: usize, row: usize, byte: u8) {
+		self.write(
+			row * Self::COLUMNS + column,
+			Self::ATTRIBUTE | u16::from(byte),
+		);
+	}
+
+	fn scroll(&mut self) {
+		for index in Self::COLUMNS..Self::COLUMNS * Self::ROWS {
+			let value = self.read(index);
+			self.write(index - Self::COLUMNS, value);
+		}
+
+		for column in 0..Self::COLUMNS {
+			self.put(column, Self::ROWS - 1, b' ');
+		}
+	}
+}
+
+unsafe impl Send for VgaText {}
@@ -0,0 +1,63 @@
+//! Parsing of virtio-mmio devices from the kernel command line.
+//!
+//! Firecracker and QEMU microvm advertise virtio-mmio devices with `virtio_mmio.device=<size>@<baseaddr>:<irq>[:<id>]` tokens.
+//! See [drivers/virtio/virtio_mmio.c — Linux source code](https://github.com/torvalds/linux/blob/v6.12/drivers/virtio/virtio_mmio.c) for details.
+
+use log::{info, warn};
+
+use crate::fdt::VirtioMmio;
+
+const PARAMETER: &str = "virtio_mmio.device=";
+
+/// Returns the virtio-mmio devices from `cmdline`.
+pub fn devices(cmdline: &str) -> impl Iterator<Item = VirtioMmio> + '_ {
+	cmdline
+		.split_ascii_whitespace()
+		.filter_map(|token| token.strip_prefix(PARAMETER))
+		.filter_map(|device| {
+			let parsed = parse_device(device);
+			match &parsed {
+				Some(VirtioMmio { addr, size, irq }) => {
+					info!("Found virtio-mmio device at {addr:#x} (size = {size:#x}, irq = {irq})")
+				}
+				None => warn!("Ignoring invalid virtio-mmio device: {device}"),
+			}
+			parsed
+		})
+}
+
+/// Parses `<size>@<baseaddr>:<irq>[:<id>]`.
+fn parse_device(device: &str) -> Option<VirtioMmio> {
+	let (size, rest) = device.split_once('@')?;
+	let (addr, rest) = rest.split_once(':')?;
+	let irq = rest.split(':').next()?;
+
+	let size = parse_size(size)?;
+	let addr = parse_int(addr)?;
+	let irq = parse_int(irq)?.try_into().ok()?;
+
+	(size != 0).then_some(VirtioMmio { addr, size, irq })
+}
+
+/// Parses an integer with an optional `K`, `M`, or `G` suffix like Linux' `memparse`.
+fn parse_size(s: &str) -> Option<u64> {
+	let (s, shift) = match s.as_bytes().last()? {
+		b'k' | b'K' => (&s[..s.len() - 1], 10),
+		b'm' | b'M' => (&s[..s.len() - 1], 20),
+		b'g' | b'G' => (&s[..s.len() - 1], 30),
+		_ => (s, 0),
+	};
+
+	parse_int(s)?.checked_mul(1 << shift)
+}
+
+/// Parses a decimal, hexadecimal (`0x`), or octal (`0`) integer like C's `strtoull` with base 0.
+fn parse_int(s: &str) -> Option<u64> {
+	if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+		u64::from_str_radix(hex, 16).ok()
+	} else if let Some(oct) = s.strip_prefix('0').filter(|oct| !oct.is_empty()) {
+		u64::from_str_radix(oct, 8).ok()
+	} else {
+		s.parse().ok()
+	}
+}
@@ -0,0 +1,190 @@
+//! Frame-pointer-based backtraces.
+//!
+//! `cargo xtask build` compiles the loader with frame pointers and embeds a symbol table into `.loader_symbols`.
+//! To size the section, it builds the loader twice and passes the size of the table from the first build as `LOADER_SYMBOLS_SIZE`.
+//! The table starts with [`MAGIC`] and the number of symbols as `u32`, followed by the symbols.
+//! Each symbol is described by its address as `u64`, its size as `u32`, and the offset of its NUL-terminated name from the start of the table as `u32`.
+//! The symbols are sorted by address.
+
+use core::ffi::CStr;
+use core::{fmt, ptr, slice};
+
+/// The maximum number of frames to print.
+const MAX_FRAMES: usize = 32;
+
+const MAGIC: &[u8; 8] = b"HLSYMTAB";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 16;
+
+/// The size of the symbol table, which is 0 for builds without `cargo xtask build`.
+const SYMBOLS_SIZE: usize = match option_env!("LOADER_SYMBOLS_SIZE") {
+	Some(size) => match usize::from_str_radix(size, 10) {
+		Ok(size) => size,
+		Err(_) => panic!("LOADER_SYMBOLS_SIZE must be a number of bytes"),
+	},
+	None => 0,
+};
+
+/// Space for the symbol table.
+#[used]
+#[link_section = ".loader_symbols"]
+static SYMBOLS: [u8; SYMBOLS_SIZE] = [0; SYMBOLS_SIZE];
+
+extern "C" {
+	static loader_symbols_start: u8;
+	static loader_symbols_end: u8;
+}
+
+/// The symbol table in `.loader_symbols`.
+struct Symbols(&'static [u8]);
+
+impl Symbols {
+	fn get() -> Option<Self> {
+		let start = ptr::addr_of!(loader_symbols_start);
+		let len = ptr::addr_of!(loader_symbols_end).addr() - start.addr();
+		// We read the table through the linker symbols, since the compiler knows the initial contents of `SYMBOLS`.
+		let table = unsafe { slice::from_raw_parts(start, len) };
+		(table.get(..MAGIC.len())? == MAGIC).then_some(Self(table))
+	}
+
+	fn read_u32(&self, offset: usize) -> Option<u32> {
+		let bytes = self.0.get(offset..offset + 4)?;
+		Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+	}
+
+	fn len(&self) -> usize {
+		self.read_u32(MAGIC.len()).unwrap() as usize
+	}
+
+	/// Returns the address, size, and name of the symbol at `index`.
+	fn symbol(&self, index: usize) -> Option<(u64, u64, &'static str)> {
+		let offset = HEADER_SIZE + index * ENTRY_SIZE;
+		let addr = u64::from_le_bytes(self.0.get(offset..offset + 8)?.try_into().unwrap());
+		let size = self.read_u32(offset + 8)?;
+		let name = self.read_u32(offset + 12)? as usize;
+		let name = CStr::from_bytes_until_nul(self.0.get(name..)?).ok()?;
+		Some((addr, size.into(), name.to_str().ok()?))
+	}
+
+	/// Returns the name of the symbol containing `addr` and the offset of `addr` in it.
+	fn lookup(&self, addr: u64) -> Option<(&'static str, u64)> {
+		let (mut low, mut high) = (0, self.len());
+		while low < high {
+			let mid = low + (high - low) / 2;
+			if self.symbol(mid)?.0 <= addr {
+				low = mid + 1;
+			} else {
+				high = mid;
+			}
+		}
+
+		let (start, size, name) = self.symbol(low.checked_sub(1)?)?;
+		let offset = addr - start;
+		(size == 0 || offset < size).then_some((name, offset))
+	}
+}
+
+/// Returns the frame pointer of the caller's frame and the return address of the frame at `fp`.
+unsafe fn unwind(fp: usize) -> (usize, usize) {
+	let fp = sptr::from_exposed_addr::<usize>(fp);
+	cfg_if::cfg_if! {
+		if #[cfg(target_arch = "riscv64")] {
+			// The frame pointer points above the saved return address and frame pointer.
+			unsafe { (fp.sub(2).read(), fp.sub(1).read()) }
+		} else {
+			// The frame pointer points to the saved frame pointer, followed by the return address.
+			unsafe { (fp.read(), fp.add(1).read()) }
+		}
+	}
+}
+
+/// The return addresses on the stack.
+struct ReturnAddresses {
+	fp: usize,
+}
+
+impl Iterator for ReturnAddresses {
+	type Item = usize;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.fp == 0 || !self.fp.is_multiple_of(align_of::<usize>()) {
+			return None;
+		}
+
+		let (next, ra) = unsafe { unwind(self.fp) };
+		// The stack grows downwards, so the callers' frames must be above.
+		self.fp = if next > self.fp { next } else { 0 };
+		(ra != 0).then_some(ra)
+	}
+}
+
+/// A backtrace, which is printed with symbols when displayed.
+pub struct Backtrace {
+	/// The program counter of interrupted code.
+	pc: Option<usize>,
+	fp: usize,
+}
+
+impl Backtrace {
+	/// Captures the backtrace of the caller.
+	#[inline(always)]
+	pub fn capture() -> Self {
+		let fp: usize;
+		unsafe {
+			cfg_if::cfg_if! {
+				if #[cfg(target_arch = "x86_64")] {
+					core::arch::asm!("mov {}, rbp", out(reg) fp, options(nomem, nostack));
+				} else if #[cfg(target_arch = "aarch64")] {
+					core::arch::asm!("mov {}, x29", out(reg) fp, options(nomem, nostack));
+				} else if #[cfg(target_arch = "riscv64")] {
+					core::arch::asm!("mv {}, s0", out(reg) fp, options(nomem, nostack));
+				}
+			}
+		}
+
+		Self { pc: None, fp }
+	}
+
+	/// Returns the backtrace of code that was interrupted at `pc` with the frame pointer `fp`.
+	pub fn interrupted(pc: u64, fp: u64) -> Self {
+		Self {
+			pc: Some(pc as usize),
+			fp: fp as usize,
+		}
+	}
+}
+
+impl fmt::Display for Backtrace {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let symbols = Symbols::get();
+
+		// Return addresses point behind the call, which might be the start of the next symbol.
+		let frames = self
+			.pc
+			.map(|pc| (pc, pc))
+			.into_iter()
+			.chain(ReturnAddresses { fp: self.fp }.map(|ra| (ra, ra - 1)))
+			.take(MAX_FRAMES);
+
+		write!(f, "Backtrace:")?;
+		for (i, (addr, lookup_addr)) in frames.enumerate() {
+			write!(f, "\n{i:>4}: {addr:#018x}")?;
+			if let Some((name, offset)) = symbols
+				.as_ref()
+				.and_then(|symbols| symbols.lookup(lookup_addr as u64))
+			{
+				let offset = offset + (addr - lookup_addr) as u64;
+				write!(f, " - {name}+{offset:#x}")?;
+			}
+		}
+
+		if symbols.is_none() {
+			write!(
+				f,
+				"\nNo symbols, build with `cargo xtask build` for symbolized backtraces"
+			)?;
+		}
+
+		Ok(())
+	}
+}
@@ -0,0 +1,129 @@
+//! Timestamps of the boot phases.
+//!
+//! The timestamps are read from the TSC on x86-64, `CNTVCT_EL0` on AArch64, and the `time` CSR on RISC-V.
+
+use core::fmt;
+
+use log::info;
+use one_shot_mutex::OneShotMutex;
+
+use crate::{arch, fdt_edit};
+
+/// A phase of booting the kernel.
+///
+/// Each timestamp is recorded at the end of the phase.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+	/// The loader has been entered.
+	Entry,
+	/// The kernel image has been found.
+	FindKernel,
+	/// The kernel image has been parsed.
+	ParseKernel,
+	/// The kernel has been loaded into memory.
+	LoadKernel,
+	/// The platform has been described in the devicetree.
+	Fdt,
+	/// The kernel is about to be entered.
+	EnterKernel,
+}
+
+impl Phase {
+	const ALL: [Self; 6] = [
+		Self::Entry,
+		Self::FindKernel,
+		Self::ParseKernel,
+		Self::LoadKernel,
+		Self::Fdt,
+		Self::EnterKernel,
+	];
+
+	/// Returns the name of the phase as in `/chosen/hermit,boot-timestamp-names`.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Entry => "entry",
+			Self::FindKernel => "find-kernel",
+			Self::ParseKernel => "parse-kernel",
+			Self::LoadKernel => "load-kernel",
+			Self::Fdt => "fdt",
+			Self::EnterKernel => "enter-kernel",
+		}
+	}
+}
+
+/// The timestamps of all phases, which are 0 for phases that have not been recorded.
+pub type Timestamps = [u64; Phase::ALL.len()];
+
+static TIMESTAMPS: OneShotMutex<Timestamps> = OneShotMutex::new([0; Phase::ALL.len()]);
+
+/// Records the current time as the end of `phase`.
+pub fn record(phase: Phase) {
+	TIMESTAMPS.lock()[phase as usize] = arch::timestamp();
+}
+
+/// Returns the timestamps recorded so far.
+pub fn timestamps() -> Timestamps {
+	*TIMESTAMPS.lock()
+}
+
+/// Returns the names of all phases in the order of [`Timestamps`].
+pub fn names() -> impl Iterator<Item = &'static str> {
+	Phase::ALL.into_iter().map(Phase::name)
+}
+
+/// Records [`Phase::EnterKernel`] and logs the durations of all phases.
+///
+/// If `fdt` contains `/chosen/hermit,boot-timestamps`, its slot for [`Phase::EnterKernel`] is updated in place.
+pub fn finish(fdt: Option<&mut [u8]>) {
+	record(Phase::EnterKernel);
+	let timestamps = *TIMESTAMPS.lock();
+
+	let property = fdt.and_then(|fdt| fdt_edit::chosen_property_mut(fdt, "hermit,boot-timestamps"));
+	if let Some(property) = property.filter(|property| property.len() == timestamps.len() * 8) {
+		// Devicetree properties are big-endian.
+		let offset = Phase::EnterKernel as usize * 8;
+		property[offset..offset + 8]
+			.copy_from_slice(&timestamps[Phase::EnterKernel as usize].to_be_bytes());
+	}
+
+	info!(
+		"Boot phases: {}",
+		Durations {
+			timestamps,
+			frequency: arch::timestamp_frequency(),
+		}
+	);
+}
+
+struct Durations {
+	timestamps: Timestamps,
+	frequency: Option<u64>,
+}
+
+impl fmt::Display for Durations {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut previous = None;
+		for (phase, timestamp) in Phase::ALL.into_iter().zip(self.timestamps) {
+			if timestamp == 0 {
+				continue;
+			}
+
+			if let Some(previous) = previous {
+				let cycles = timestamp.wrapping_sub(previous);
+				write!(f, ", {} ", phase.name())?;
+				match self.frequency {
+					Some(frequency) => {
+						let micros = u128::from(cycles) * 1_000_000 / u128::from(frequency);
+						write!(f, "{micros} us")?;
+					}
+					None => write!(f, "{cycles} cycles")?,
+				}
+			} else {
+				write!(f, "{} at {timestamp}", phase.name())?;
+			}
+			previous = Some(timestamp);
+		}
+
+		Ok(())
+	}
+}
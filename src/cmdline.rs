@@ -0,0 +1,44 @@
+//! Loader options from the boot command line.
+//!
+//! Loader options are kernel arguments, which are separated from application arguments by `--`.
+
+use one_shot_mutex::OneShotMutex;
+
+static EDITED: OneShotMutex<Option<&'static str>> = OneShotMutex::new(None);
+
+/// Replaces the command line from the boot protocol for the kernel.
+pub fn set_edited(cmdline: &'static str) {
+	*EDITED.lock() = Some(cmdline);
+}
+
+/// Returns the command line if it has been replaced by [`set_edited`].
+pub fn edited() -> Option<&'static str> {
+	*EDITED.lock()
+}
+
+/// Returns the kernel arguments of `cmdline`.
+fn kernel_args(cmdline: &str) -> impl Iterator<Item = &str> {
+	cmdline
+		.split_ascii_whitespace()
+		.take_while(|arg| *arg != "--")
+}
+
+/// Returns whether `cmdline` contains the argument `flag`.
+pub fn flag(cmdline: &str, flag: &str) -> bool {
+	kernel_args(cmdline).any(|arg| arg == flag)
+}
+
+/// Returns the environment variables for the application from all `loader.env.<KEY>=<VALUE>` arguments in `cmdline` as `<KEY>=<VALUE>`.
+pub fn env(cmdline: &str) -> impl Iterator<Item = &str> {
+	kernel_args(cmdline)
+		.filter_map(|arg| arg.strip_prefix("loader.env."))
+		.filter(|var| var.split_once('=').is_some_and(|(key, _)| !key.is_empty()))
+}
+
+/// Returns the values of all `key=value` arguments in `cmdline`.
+pub fn values<'a>(cmdline: &'a str, key: &'a str) -> impl Iterator<Item = &'a str> {
+	kernel_args(cmdline)
+		.filter_map(|arg| arg.split_once('='))
+		.filter(move |(k, _)| *k == key)
+		.map(|(_, value)| value)
+}
@@ -0,0 +1,134 @@
+//! The configuration file of the loader.
+//!
+//! On UEFI, the loader reads `\efi\boot\hermit-loader.conf` from its own file system if it exists.
+//! The file consists of sections, which start with a `[<SECTION>]` line, and `<KEY>=<VALUE>` lines.
+//! Empty lines and lines starting with `#` are ignored.
+//!
+//! The `[env]` section contains environment variables for the application (see [`cmdline::env`]).
+//! `loader.env.<KEY>=<VALUE>` arguments on the command line take precedence over the configuration file.
+
+use alloc::vec::Vec;
+
+use log::warn;
+
+use crate::cmdline;
+
+/// The parsed configuration file.
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct Config<'a> {
+	/// The environment variables from `[env]` as `<KEY>=<VALUE>`.
+	env: Vec<&'a str>,
+}
+
+impl<'a> Config<'a> {
+	/// Parses the configuration file `text`, skipping invalid lines.
+	pub fn parse(text: &'a str) -> Self {
+		let mut config = Self::default();
+		let mut section = None;
+
+		for (number, line) in text
+			.lines()
+			.enumerate()
+			.map(|(i, line)| (i + 1, line.trim()))
+		{
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			if let Some(name) = line
+				.strip_prefix('[')
+				.and_then(|line| line.strip_suffix(']'))
+			{
+				section = Some(name.trim());
+				if section != Some("env") {
+					warn!("Ignoring unknown section [{name}] in the configuration file");
+				}
+				continue;
+			}
+
+			let valid = line
+				.split_once('=')
+				.is_some_and(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace));
+			match section {
+				Some("env") if valid => config.env.push(line),
+				Some("env") | None => {
+					warn!("Ignoring invalid line {number} of the configuration file: {line}");
+				}
+				Some(_) => {}
+			}
+		}
+
+		config
+	}
+}
+
+/// Returns the environment variables for the application from `config` and `cmdline`.
+///
+/// Variables from `cmdline` replace variables with the same key from `config`.
+pub fn env<'a>(config: Option<&Config<'a>>, cmdline: Option<&'a str>) -> Vec<&'a str> {
+	fn key(var: &str) -> &str {
+		var.split_once('=').map(|(key, _)| key).unwrap()
+	}
+
+	let cmdline_env = cmdline
+		.into_iter()
+		.flat_map(cmdline::env)
+		.collect::<Vec<_>>();
+	let config_env = config
+		.into_iter()
+		.flat_map(|config| config.env.iter().copied())
+		.filter(|var| !cmdline_env.iter().any(|other| key(other) == key(var)));
+
+	config_env.chain(cmdline_env.iter().copied()).collect()
+}
+
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse() {
+		let config = Config::parse(
+			"# Configuration\n\
+			 \n\
+			 [env]\n\
+			 RUST_LOG=info\n\
+			 \tGREETING=Hello, world!  \n\
+			 EMPTY=\n",
+		);
+		assert_eq!(
+			config.env,
+			["RUST_LOG=info", "GREETING=Hello, world!", "EMPTY="]
+		);
+	}
+
+	#[test]
+	fn parse_invalid_lines() {
+		let config = Config::parse(
+			"OUTSIDE=section\n\
+			 [other]\n\
+			 KEY=other\n\
+			 [env]\n\
+			 =value\n\
+			 KEY = value\n\
+			 no value\n\
+			 [ env ]\n\
+			 KEY=value\n",
+		);
+		assert_eq!(config.env, ["KEY=value"]);
+	}
+
+	#[test]
+	fn env_precedence() {
+		let config = Config::parse("[env]\nA=config\nB=config\n");
+		assert_eq!(
+			env(
+				Some(&config),
+				Some("loader.env.B=cmdline loader.env.C=cmdline -- B=app")
+			),
+			["A=config", "B=cmdline", "C=cmdline"]
+		);
+		assert_eq!(env(Some(&config), None), ["A=config", "B=config"]);
+		assert_eq!(env(None, Some("loader.env.C=cmdline")), ["C=cmdline"]);
+	}
+}
@@ -0,0 +1,69 @@
+//! Random numbers from the firmware and the processor.
+
+use sha2::{Digest, Sha256};
+
+use crate::arch;
+
+/// The size of the seed for the kernel's random number generator in bytes.
+pub const SEED_SIZE: usize = 64;
+
+/// Fills `buf` with random bytes.
+///
+/// Returns `false` if no entropy source is available.
+/// On UEFI, this must be called before exiting boot services to use the `EFI_RNG_PROTOCOL`.
+pub fn fill(buf: &mut [u8]) -> bool {
+	#[cfg(target_os = "uefi")]
+	if crate::os::fill_random(buf) {
+		return true;
+	}
+
+	buf.chunks_mut(8).all(|chunk| {
+		let Some(value) = arch::random_u64() else {
+			return false;
+		};
+		chunk.copy_from_slice(&value.to_ne_bytes()[..chunk.len()]);
+		true
+	})
+}
+
+/// Returns a random `u64`.
+pub fn random_u64() -> Option<u64> {
+	let mut bytes = [0; 8];
+	fill(&mut bytes).then(|| u64::from_ne_bytes(bytes))
+}
+
+/// Returns a random seed for KASLR from the firmware or the processor.
+pub fn kaslr_seed() -> Option<u64> {
+	#[cfg(not(target_arch = "x86_64"))]
+	if let Some(seed) = arch::firmware_seed() {
+		return Some(seed);
+	}
+
+	random_u64()
+}
+
+/// Returns a seed for the kernel's random number generator.
+pub fn seed() -> Option<[u8; SEED_SIZE]> {
+	let mut seed = [0; SEED_SIZE];
+	fill(&mut seed).then_some(seed)
+}
+
+/// Derives a new seed from `seed` and random bytes from the processor if available.
+///
+/// The new seed does not reveal `seed`, which may have been used for something else, e.g., KASLR.
+#[cfg_attr(target_arch = "x86_64", expect(dead_code))]
+pub fn stir(seed: &[u8]) -> [u8; SEED_SIZE] {
+	let mut random = [0; SEED_SIZE];
+	fill(&mut random);
+
+	let mut stirred = [0; SEED_SIZE];
+	for (i, chunk) in stirred.chunks_mut(32).enumerate() {
+		let digest = Sha256::new()
+			.chain_update([i as u8])
+			.chain_update(seed)
+			.chain_update(random)
+			.finalize();
+		chunk.copy_from_slice(&digest);
+	}
+	stirred
+}
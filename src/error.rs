@@ -0,0 +1,147 @@
+//! Errors that prevent the loader from booting the kernel.
+
+use core::fmt;
+
+use hermit_entry::elf::ParseKernelError;
+
+use crate::signature::SignatureError;
+
+/// An error that prevents the loader from booting the kernel.
+#[derive(Debug)]
+pub enum LoaderError {
+	/// The boot protocol did not provide what the loader expected.
+	#[cfg_attr(target_os = "uefi", expect(dead_code))]
+	BootProtocol {
+		/// The boot protocol, e.g., `Multiboot`.
+		protocol: &'static str,
+		/// The address of the offending boot information, if known.
+		addr: Option<u64>,
+		/// What the loader expected to find.
+		expected: &'static str,
+	},
+	/// No usable memory was found.
+	#[cfg_attr(target_os = "uefi", expect(dead_code))]
+	Memory {
+		/// The boot protocol that should describe the memory.
+		protocol: &'static str,
+		/// What the loader expected to find.
+		expected: &'static str,
+	},
+	/// The kernel image is not a valid Hermit ELF file.
+	Image(ParseKernelError),
+	/// The signature of the kernel image could not be verified.
+	Signature(SignatureError),
+	/// The devicetree for the kernel could not be created.
+	#[cfg(any(target_os = "uefi", target_arch = "x86_64"))]
+	Fdt(vm_fdt::Error),
+	/// A UEFI service failed.
+	#[cfg(target_os = "uefi")]
+	Uefi {
+		/// What the loader tried to do.
+		context: &'static str,
+		error: uefi::Error,
+	},
+	/// A loader option on the command line is invalid.
+	#[cfg(target_os = "uefi")]
+	Option {
+		/// The name of the option, e.g., `loader.fs`.
+		name: &'static str,
+		/// The values that the loader expected.
+		expected: &'static str,
+	},
+	/// A file could not be read with UEFI.
+	#[cfg(target_os = "uefi")]
+	File(uefi::fs::Error),
+	/// A file could not be downloaded with UEFI.
+	#[cfg(target_os = "uefi")]
+	Download {
+		url: alloc::string::String,
+		error: uefi::Error,
+	},
+}
+
+impl LoaderError {
+	/// Returns the UEFI status for returning to the firmware.
+	#[cfg(target_os = "uefi")]
+	pub fn status(&self) -> uefi::Status {
+		use uefi::Status;
+
+		match self {
+			Self::BootProtocol { .. } => Status::NOT_FOUND,
+			Self::Memory { .. } => Status::OUT_OF_RESOURCES,
+			Self::Image(_) => Status::LOAD_ERROR,
+			Self::Signature(_) => Status::SECURITY_VIOLATION,
+			Self::Fdt(_) => Status::ABORTED,
+			Self::Uefi { error, .. } => error.status(),
+			Self::Option { .. } => Status::INVALID_PARAMETER,
+			Self::File(uefi::fs::Error::Io(err)) => err.uefi_error.status(),
+			Self::File(_) => Status::INVALID_PARAMETER,
+			Self::Download { error, .. } => error.status(),
+		}
+	}
+}
+
+impl fmt::Display for LoaderError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::BootProtocol {
+				protocol,
+				addr,
+				expected,
+			} => {
+				write!(f, "{protocol}: expected {expected}")?;
+				if let Some(addr) = addr {
+					write!(f, " at {addr:#x}")?;
+				}
+				Ok(())
+			}
+			Self::Memory { protocol, expected } => {
+				write!(
+					f,
+					"Could not find usable memory: {protocol}: expected {expected}"
+				)
+			}
+			Self::Image(err) => write!(f, "Could not load the kernel: {err}"),
+			Self::Signature(err) => write!(f, "Refusing to boot: {err}"),
+			#[cfg(any(target_os = "uefi", target_arch = "x86_64"))]
+			Self::Fdt(err) => write!(f, "Could not create the devicetree: {err}"),
+			#[cfg(target_os = "uefi")]
+			Self::Uefi { context, error } => write!(f, "UEFI: {context} failed: {error}"),
+			#[cfg(target_os = "uefi")]
+			Self::Option { name, expected } => {
+				write!(f, "Invalid {name}: expected {expected}")
+			}
+			#[cfg(target_os = "uefi")]
+			Self::File(uefi::fs::Error::Io(err)) => write!(
+				f,
+				"UEFI: {:?} of \"{}\" failed: {}",
+				err.context, err.path, err.uefi_error
+			),
+			#[cfg(target_os = "uefi")]
+			Self::File(err) => write!(f, "UEFI: Could not read file: {err}"),
+			#[cfg(target_os = "uefi")]
+			Self::Download { url, error } => {
+				write!(f, "UEFI: Downloading \"{url}\" failed: {error}")
+			}
+		}
+	}
+}
+
+impl From<ParseKernelError> for LoaderError {
+	fn from(err: ParseKernelError) -> Self {
+		Self::Image(err)
+	}
+}
+
+impl From<SignatureError> for LoaderError {
+	fn from(err: SignatureError) -> Self {
+		Self::Signature(err)
+	}
+}
+
+#[cfg(any(target_os = "uefi", target_arch = "x86_64"))]
+impl From<vm_fdt::Error> for LoaderError {
+	fn from(err: vm_fdt::Error) -> Self {
+		Self::Fdt(err)
+	}
+}
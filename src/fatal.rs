@@ -0,0 +1,111 @@
+//! Stopping the machine after fatal errors.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use one_shot_mutex::OneShotMutex;
+
+use crate::{arch, cmdline};
+
+/// What to do after a fatal error.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Action {
+	/// Stop the processor.
+	Halt,
+	/// Reset the machine.
+	Reboot,
+	/// Turn off the machine with a failure status if possible.
+	Poweroff,
+	/// Exit QEMU with a failure status using a debug device that must be present.
+	DebugExit,
+}
+
+impl Action {
+	const fn parse(value: &str) -> Option<Self> {
+		if str_eq(value, "halt") {
+			Some(Self::Halt)
+		} else if str_eq(value, "reboot") {
+			Some(Self::Reboot)
+		} else if str_eq(value, "poweroff") {
+			Some(Self::Poweroff)
+		} else if str_eq(value, "debug-exit") {
+			Some(Self::DebugExit)
+		} else {
+			None
+		}
+	}
+}
+
+/// Compares strings in const contexts, where `==` is not available.
+const fn str_eq(a: &str, b: &str) -> bool {
+	let (a, b) = (a.as_bytes(), b.as_bytes());
+	if a.len() != b.len() {
+		return false;
+	}
+	let mut i = 0;
+	while i < a.len() {
+		if a[i] != b[i] {
+			return false;
+		}
+		i += 1;
+	}
+	true
+}
+
+/// The action if `loader.fatal=` is not given, which can be set at build time with `LOADER_FATAL`.
+const DEFAULT_ACTION: Action = match option_env!("LOADER_FATAL") {
+	Some(value) => match Action::parse(value) {
+		Some(action) => action,
+		None => panic!("LOADER_FATAL must be halt, reboot, poweroff, or debug-exit"),
+	},
+	None => Action::Poweroff,
+};
+
+static ACTION: OneShotMutex<Action> = OneShotMutex::new(DEFAULT_ACTION);
+
+/// Configures the action after fatal errors from `loader.fatal=` in `cmdline`.
+///
+/// Returns the value if it is not `halt`, `reboot`, `poweroff`, or `debug-exit`.
+pub fn init(cmdline: &'static str) -> Result<(), &'static str> {
+	let Some(value) = cmdline::values(cmdline, "loader.fatal").last() else {
+		return Ok(());
+	};
+
+	*ACTION.lock() = Action::parse(value).ok_or(value)?;
+
+	Ok(())
+}
+
+/// Stops the machine as configured by [`init`].
+///
+/// This must only be called after the error has been reported.
+pub fn fatal() -> ! {
+	// Do not panic again if we are called from a panic while the lock is held.
+	let action = ACTION.try_lock().map_or(DEFAULT_ACTION, |action| *action);
+
+	match action {
+		Action::Halt => arch::halt(),
+		Action::Reboot => arch::reboot(),
+		Action::Poweroff => arch::poweroff(),
+		Action::DebugExit => arch::debug_exit(),
+	}
+}
+
+/// Reports an error that interrupted the loader, like a CPU exception, and stops the machine.
+///
+/// This writes to the console directly, since the logger might not be initialized yet and the interrupted code might hold the console lock.
+pub fn report(args: fmt::Arguments<'_>) -> ! {
+	static REPORTING: AtomicBool = AtomicBool::new(false);
+
+	// Do not report errors that occur while reporting.
+	if !REPORTING.swap(true, Ordering::Relaxed) {
+		let console = &crate::os::CONSOLE;
+		if console.is_locked() {
+			// SAFETY: The interrupted code never resumes.
+			unsafe { console.force_unlock() };
+		}
+		writeln!(console.lock(), "[LOADER] {args}").ok();
+	}
+
+	fatal()
+}
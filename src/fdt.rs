@@ -1,13 +1,52 @@
 use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::ops::Range;
 
 use vm_fdt::{FdtWriter, FdtWriterNode, FdtWriterResult};
 
+use crate::boot_time::{self, Phase};
+use crate::entropy::SEED_SIZE;
+use crate::{measured_boot, slot};
+
 pub struct Fdt<'a> {
 	writer: FdtWriter,
 	root_node: FdtWriterNode,
 	bootargs: Option<&'a str>,
+	cpus: Vec<Cpu>,
+	timebase_frequency: Option<u64>,
+	stdout_path: Option<String>,
+	rng_seed: Option<[u8; SEED_SIZE]>,
+	env: Vec<&'a str>,
+	/// The address of the UEFI system table.
+	uefi_system_table: Option<u64>,
+	/// The memory of the UEFI runtime services.
+	uefi_runtime_regions: Vec<Range<u64>>,
+	/// Whether `/cpus`, `/reserved-memory`, and `/chosen` have been written by [`Fdt::prebuild`].
+	prebuilt: bool,
+}
+
+/// A CPU as described in `/cpus`.
+pub struct Cpu {
+	/// The hardware ID of the CPU, e.g., the local APIC ID on x86-64.
+	pub id: u32,
+	/// Whether the CPU is usable.
+	pub enabled: bool,
+}
+
+/// An I/O APIC.
+pub struct IoApic {
+	pub id: u8,
+	pub addr: u64,
+	/// The first global system interrupt handled by this I/O APIC.
+	pub gsi_base: u32,
+}
+
+/// A virtio-mmio device.
+pub struct VirtioMmio {
+	pub addr: u64,
+	pub size: u64,
+	pub irq: u32,
 }
 
 impl<'a> Fdt<'a> {
@@ -20,27 +59,164 @@ impl<'a> Fdt<'a> {
 		writer.property_u32("#size-cells", 0x2)?;
 
 		let bootargs = None;
+		let cpus = Vec::new();
+		let timebase_frequency = None;
+		let stdout_path = None;
+		let rng_seed = None;
+		let env = Vec::new();
+		let uefi_system_table = None;
+		let uefi_runtime_regions = Vec::new();
+		let prebuilt = false;
 
 		Ok(Self {
 			writer,
 			root_node,
 			bootargs,
+			cpus,
+			timebase_frequency,
+			stdout_path,
+			rng_seed,
+			env,
+			uefi_system_table,
+			uefi_runtime_regions,
+			prebuilt,
 		})
 	}
 
+	/// Writes `/cpus`, `/reserved-memory`, and `/chosen`, after which only memory nodes may be added.
+	///
+	/// This allows building most of the devicetree before exiting boot services on UEFI.
+	#[cfg_attr(not(target_os = "uefi"), expect(unused))]
+	pub fn prebuild(mut self) -> FdtWriterResult<Self> {
+		self.write_pending_nodes()?;
+		self.prebuilt = true;
+
+		Ok(self)
+	}
+
 	pub fn finish(mut self) -> FdtWriterResult<Vec<u8>> {
+		if !self.prebuilt {
+			self.write_pending_nodes()?;
+		}
+
+		self.writer.end_node(self.root_node)?;
+
+		self.writer.finish()
+	}
+
+	fn write_pending_nodes(&mut self) -> FdtWriterResult<()> {
+		boot_time::record(Phase::Fdt);
+
+		if !self.cpus.is_empty() || self.timebase_frequency.is_some() {
+			let cpus_node = self.writer.begin_node("cpus")?;
+			self.writer.property_u32("#address-cells", 0x1)?;
+			self.writer.property_u32("#size-cells", 0x0)?;
+
+			if let Some(timebase_frequency) = self.timebase_frequency {
+				match u32::try_from(timebase_frequency) {
+					Ok(timebase_frequency) => self
+						.writer
+						.property_u32("timebase-frequency", timebase_frequency)?,
+					Err(_) => self
+						.writer
+						.property_u64("timebase-frequency", timebase_frequency)?,
+				}
+			}
+
+			for cpu in &self.cpus {
+				let cpu_node = self.writer.begin_node(&format!("cpu@{:x}", cpu.id))?;
+				self.writer.property_string("device_type", "cpu")?;
+				self.writer.property_u32("reg", cpu.id)?;
+				let status = if cpu.enabled { "okay" } else { "disabled" };
+				self.writer.property_string("status", status)?;
+				self.writer.end_node(cpu_node)?;
+			}
+
+			self.writer.end_node(cpus_node)?;
+		}
+
+		// The loader log is recorded until the kernel is entered and has to be kept by the kernel.
+		let log_buffer = crate::log_buffer::region();
+		let reserved_memory_node = self.writer.begin_node("reserved-memory")?;
+		self.writer.property_u32("#address-cells", 0x2)?;
+		self.writer.property_u32("#size-cells", 0x2)?;
+		self.writer.property_null("ranges")?;
+		let loader_log_node = self
+			.writer
+			.begin_node(&format!("loader-log@{:x}", log_buffer.start))?;
+		self.writer
+			.property_string("compatible", "hermit,loader-log")?;
+		self.writer.property_array_u64(
+			"reg",
+			&[log_buffer.start, log_buffer.end - log_buffer.start],
+		)?;
+		self.writer.end_node(loader_log_node)?;
+		// The kernel needs the UEFI runtime services for marking a boot of a slot successful.
+		for region in &self.uefi_runtime_regions {
+			let uefi_runtime_node = self
+				.writer
+				.begin_node(&format!("uefi-runtime@{:x}", region.start))?;
+			self.writer
+				.property_string("compatible", "hermit,uefi-runtime")?;
+			self.writer
+				.property_array_u64("reg", &[region.start, region.end - region.start])?;
+			self.writer.end_node(uefi_runtime_node)?;
+		}
+		self.writer.end_node(reserved_memory_node)?;
+
 		let chosen_node = self.writer.begin_node("chosen")?;
 		if let Some(bootargs) = self.bootargs {
 			self.writer.property_string("bootargs", bootargs)?;
 		}
+		if let Some(stdout_path) = &self.stdout_path {
+			self.writer.property_string("stdout-path", stdout_path)?;
+		}
+		if let Some(rng_seed) = &self.rng_seed {
+			self.writer.property("rng-seed", rng_seed)?;
+		}
+		if !self.env.is_empty() {
+			self.writer.property_string_list(
+				"hermit,env",
+				self.env.iter().copied().map(String::from).collect(),
+			)?;
+		}
+		// The timestamp for entering the kernel is filled in by `boot_time::finish`.
+		self.writer
+			.property_array_u64("hermit,boot-timestamps", &boot_time::timestamps())?;
+		self.writer.property_string_list(
+			"hermit,boot-timestamp-names",
+			boot_time::names().map(String::from).collect(),
+		)?;
+		if let Some(timebase_frequency) = self.timebase_frequency {
+			self.writer
+				.property_u64("hermit,boot-timestamp-frequency", timebase_frequency)?;
+		}
+		if let Some(digest) = measured_boot::image() {
+			self.writer.property("hermit,app-sha256", &digest)?;
+		}
+		if let Some(digest) = measured_boot::cmdline() {
+			self.writer.property("hermit,cmdline-sha256", &digest)?;
+		}
+		if let Some(digest) = measured_boot::env() {
+			self.writer.property("hermit,env-sha256", &digest)?;
+		}
+		if let Some(uefi_system_table) = self.uefi_system_table {
+			self.writer
+				.property_u64("hermit,uefi-system-table", uefi_system_table)?;
+		}
+		if let Some(booted) = slot::booted() {
+			self.writer
+				.property_string("hermit,boot-slot", booted.slot.name())?;
+			if let Some(state_variable) = booted.state_variable {
+				self.writer
+					.property_string("hermit,boot-state-variable", state_variable)?;
+			}
+		}
 		self.writer.end_node(chosen_node)?;
 
-		self.writer.end_node(self.root_node)?;
-
-		self.writer.finish()
+		Ok(())
 	}
 
-	#[cfg_attr(target_os = "uefi", expect(unused))]
 	pub fn bootargs(mut self, bootargs: &'a str) -> FdtWriterResult<Self> {
 		assert!(self.bootargs.is_none());
 		self.bootargs = Some(bootargs);
@@ -48,7 +224,37 @@ impl<'a> Fdt<'a> {
 		Ok(self)
 	}
 
-	#[cfg_attr(all(target_arch = "x86_64", not(target_os = "uefi")), expect(unused))]
+	pub fn cpus(mut self, cpus: impl IntoIterator<Item = Cpu>) -> FdtWriterResult<Self> {
+		assert!(self.cpus.is_empty());
+		self.cpus.extend(cpus);
+
+		Ok(self)
+	}
+
+	/// Sets the frequency of the CPU timebase in Hz, e.g., the TSC on x86-64.
+	pub fn timebase_frequency(mut self, frequency: u64) -> FdtWriterResult<Self> {
+		assert!(self.timebase_frequency.is_none());
+		self.timebase_frequency = Some(frequency);
+
+		Ok(self)
+	}
+
+	/// Sets the environment variables of the application in `/chosen/hermit,env`, each as `<KEY>=<VALUE>`.
+	pub fn env(mut self, vars: impl IntoIterator<Item = &'a str>) -> FdtWriterResult<Self> {
+		assert!(self.env.is_empty());
+		self.env.extend(vars);
+
+		Ok(self)
+	}
+
+	/// Sets the seed for the kernel's random number generator in `/chosen/rng-seed`.
+	pub fn rng_seed(mut self, seed: [u8; SEED_SIZE]) -> FdtWriterResult<Self> {
+		assert!(self.rng_seed.is_none());
+		self.rng_seed = Some(seed);
+
+		Ok(self)
+	}
+
 	pub fn rsdp(mut self, rsdp: u64) -> FdtWriterResult<Self> {
 		let rsdp_node = self.writer.begin_node(&format!("hermit,rsdp@{rsdp:x}"))?;
 		self.writer.property_array_u64("reg", &[rsdp, 1])?;
@@ -57,6 +263,79 @@ impl<'a> Fdt<'a> {
 		Ok(self)
 	}
 
+	/// Adds an ns16550a-compatible serial port at I/O port `port` and uses it as `/chosen/stdout-path`.
+	///
+	/// The serial port is placed on an ISA bus node, which encodes I/O ports with a first address cell of 1.
+	pub fn serial_port(mut self, port: u16, irq: u32, baud_rate: u32) -> FdtWriterResult<Self> {
+		/// Clock frequency of the standard PC UART.
+		const CLOCK_FREQUENCY: u32 = 1_843_200;
+
+		assert!(self.stdout_path.is_none());
+
+		let isa_node = self.writer.begin_node("isa")?;
+		self.writer.property_string("compatible", "isa")?;
+		self.writer.property_u32("#address-cells", 0x2)?;
+		self.writer.property_u32("#size-cells", 0x1)?;
+
+		let name = format!("serial@i{port:x}");
+		let serial_node = self.writer.begin_node(&name)?;
+		self.writer.property_string("compatible", "ns16550a")?;
+		self.writer
+			.property_array_u32("reg", &[0x1, port.into(), 0x8])?;
+		self.writer.property_u32("interrupts", irq)?;
+		self.writer
+			.property_u32("clock-frequency", CLOCK_FREQUENCY)?;
+		self.writer.property_u32("current-speed", baud_rate)?;
+		self.writer.end_node(serial_node)?;
+
+		self.writer.end_node(isa_node)?;
+
+		self.stdout_path = Some(format!("/isa/{name}:{baud_rate}"));
+
+		Ok(self)
+	}
+
+	pub fn hpet(mut self, addr: u64) -> FdtWriterResult<Self> {
+		let hpet_node = self.writer.begin_node(&format!("hpet@{addr:x}"))?;
+		self.writer.property_string("compatible", "intel,hpet")?;
+		self.writer.property_array_u64("reg", &[addr, 0x400])?;
+		self.writer.end_node(hpet_node)?;
+
+		Ok(self)
+	}
+
+	pub fn io_apic(mut self, io_apic: IoApic) -> FdtWriterResult<Self> {
+		let io_apic_node = self
+			.writer
+			.begin_node(&format!("ioapic@{:x}", io_apic.addr))?;
+		self.writer.property_string("compatible", "intel,ioapic")?;
+		self.writer
+			.property_array_u64("reg", &[io_apic.addr, 0x1000])?;
+		self.writer.property_null("interrupt-controller")?;
+		self.writer.property_u32("#interrupt-cells", 0x2)?;
+		self.writer
+			.property_u32("hermit,apic-id", io_apic.id.into())?;
+		self.writer
+			.property_u32("hermit,gsi-base", io_apic.gsi_base)?;
+		self.writer.end_node(io_apic_node)?;
+
+		Ok(self)
+	}
+
+	#[cfg_attr(target_os = "uefi", expect(unused))]
+	pub fn virtio_mmio(mut self, device: VirtioMmio) -> FdtWriterResult<Self> {
+		let virtio_mmio_node = self
+			.writer
+			.begin_node(&format!("virtio_mmio@{:x}", device.addr))?;
+		self.writer.property_string("compatible", "virtio,mmio")?;
+		self.writer
+			.property_array_u64("reg", &[device.addr, device.size])?;
+		self.writer.property_u32("interrupts", device.irq)?;
+		self.writer.end_node(virtio_mmio_node)?;
+
+		Ok(self)
+	}
+
 	pub fn memory(mut self, memory: Range<u64>) -> FdtWriterResult<Self> {
 		let memory_node = self
 			.writer
@@ -97,27 +376,124 @@ mod x86_64 {
 
 #[cfg(target_os = "uefi")]
 mod uefi {
+	use alloc::vec::Vec;
 	use core::fmt;
 	use core::fmt::Write;
+	use core::ops::Range;
 
-	use log::info;
+	use log::{info, warn};
 	use uefi::boot::{MemoryDescriptor, MemoryType, PAGE_SIZE};
 	use uefi::mem::memory_map::{MemoryMap, MemoryMapMut};
 	use vm_fdt::FdtWriterResult;
 
+	/// An upper bound for the size of the devicetree without CPU and memory nodes.
+	const BASE_SIZE: usize = 0x1000;
+
+	/// An upper bound for the size of a CPU node.
+	const CPU_NODE_SIZE: usize = 0x60;
+
+	/// An upper bound for the size of a memory node.
+	const MEMORY_NODE_SIZE: usize = 0x50;
+
+	/// An upper bound for the size of a UEFI runtime node in `/reserved-memory`.
+	const UEFI_RUNTIME_NODE_SIZE: usize = 0x80;
+
+	/// An upper bound for the temporary allocations when writing a memory node.
+	///
+	/// This includes the node name, the property names and values, and the memory region.
+	const MEMORY_NODE_TEMPORARIES: usize = 0x80;
+
 	impl super::Fdt<'_> {
-		pub fn memory_map(mut self, memory_map: &mut impl MemoryMapMut) -> FdtWriterResult<Self> {
+		/// Returns the size of the heap that is needed for adding `memory_nodes` memory nodes and finishing the devicetree.
+		///
+		/// This is used for sizing the heap after exiting boot services.
+		pub fn heap_size(&self, memory_nodes: usize) -> usize {
+			let size = BASE_SIZE
+				+ self.bootargs.map_or(0, str::len)
+				+ self.env.iter().map(|var| var.len() + 1).sum::<usize>()
+				+ self.cpus.len() * CPU_NODE_SIZE
+				+ self.uefi_runtime_regions.len() * UEFI_RUNTIME_NODE_SIZE
+				+ memory_nodes * MEMORY_NODE_SIZE;
+
+			// Growing the devicetree allocates up to twice its final size for the new buffer,
+			// and the previous buffers are never reused by the bump allocator.
+			4 * size + memory_nodes * MEMORY_NODE_TEMPORARIES
+		}
+
+		/// Passes the UEFI runtime services to the kernel.
+		///
+		/// This adds the address of `system_table` to `/chosen` and the runtime services memory in `memory_map` to `/reserved-memory`.
+		/// The loader does not call `SetVirtualAddressMap`, so the kernel has to call the runtime services with this memory identity-mapped.
+		pub fn uefi_runtime(
+			mut self,
+			system_table: u64,
+			memory_map: &impl MemoryMap,
+		) -> FdtWriterResult<Self> {
+			assert!(!self.prebuilt);
+			self.uefi_system_table = Some(system_table);
+
+			let entries = memory_map
+				.entries()
+				.filter(|entry| {
+					matches!(
+						entry.ty,
+						MemoryType::RUNTIME_SERVICES_CODE | MemoryType::RUNTIME_SERVICES_DATA
+					)
+				})
+				.map(|entry| {
+					entry.phys_start..entry.phys_start + entry.page_count * PAGE_SIZE as u64
+				});
+			for entry in entries {
+				match self.uefi_runtime_regions.last_mut() {
+					Some(last) if last.end == entry.start => last.end = entry.end,
+					_ => self.uefi_runtime_regions.push(entry),
+				}
+			}
+
+			Ok(self)
+		}
+
+		/// Adds memory nodes for the conventional memory in `memory_map`.
+		///
+		/// Adjacent entries are merged.
+		/// If there are more than `max_nodes` memory regions, e.g., because the memory map changed after sizing the heap, the smallest regions are left out.
+		pub fn memory_map(
+			mut self,
+			memory_map: &mut impl MemoryMapMut,
+			max_nodes: usize,
+		) -> FdtWriterResult<Self> {
 			memory_map.sort();
 			info!("Memory map:\n{}", memory_map.display());
 
 			let entries = memory_map
 				.entries()
-				.filter(|entry| entry.ty == MemoryType::CONVENTIONAL);
+				.filter(|entry| entry.ty == MemoryType::CONVENTIONAL)
+				.map(|entry| {
+					entry.phys_start..entry.phys_start + entry.page_count * PAGE_SIZE as u64
+				});
 
+			let mut regions: Vec<Range<u64>> = Vec::with_capacity(memory_map.len());
 			for entry in entries {
-				self = self.memory(
-					entry.phys_start..entry.phys_start + entry.page_count * PAGE_SIZE as u64,
-				)?;
+				match regions.last_mut() {
+					Some(last) if last.end == entry.start => last.end = entry.end,
+					_ => regions.push(entry),
+				}
+			}
+
+			if regions.len() > max_nodes {
+				warn!(
+					"Leaving out {} of {} memory regions",
+					regions.len() - max_nodes,
+					regions.len()
+				);
+				regions
+					.sort_unstable_by_key(|region| core::cmp::Reverse(region.end - region.start));
+				regions.truncate(max_nodes);
+				regions.sort_unstable_by_key(|region| region.start);
+			}
+
+			for region in regions {
+				self = self.memory(region)?;
 			}
 
 			Ok(self)
@@ -0,0 +1,797 @@
+//! In-place reading and editing of flattened devicetrees.
+//!
+//! See [Flattened Devicetree (DTB) Format — Devicetree Specification](https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html) for the format.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use align_address::Align;
+
+const MAGIC: u32 = 0xd00d_feed;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Offsets of the header fields.
+const TOTALSIZE: usize = 0x04;
+const OFF_DT_STRUCT: usize = 0x08;
+const OFF_DT_STRINGS: usize = 0x0c;
+const OFF_MEM_RSVMAP: usize = 0x10;
+const VERSION: usize = 0x14;
+const SIZE_DT_STRINGS: usize = 0x20;
+const SIZE_DT_STRUCT: usize = 0x24;
+
+fn read_u32(fdt: &[u8], offset: usize) -> Option<u32> {
+	let bytes = fdt.get(offset..offset + 4)?;
+	Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn write_u32(fdt: &mut [u8], offset: usize, value: u32) {
+	fdt[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Returns the NUL-terminated string at `offset`.
+fn read_str(fdt: &[u8], offset: usize) -> Option<&[u8]> {
+	let bytes = fdt.get(offset..)?;
+	let len = bytes.iter().position(|&byte| byte == 0)?;
+	Some(&bytes[..len])
+}
+
+/// The layout of a devicetree.
+struct Header {
+	totalsize: usize,
+	off_dt_struct: usize,
+	off_dt_strings: usize,
+	size_dt_struct: usize,
+	size_dt_strings: usize,
+}
+
+impl Header {
+	fn parse(fdt: &[u8]) -> Result<Self, &'static str> {
+		let field = |offset| {
+			read_u32(fdt, offset)
+				.map(|value| value as usize)
+				.ok_or("truncated header")
+		};
+
+		if field(0)? != MAGIC as usize {
+			return Err("invalid magic");
+		}
+		// The sizes of the blocks were added in version 17.
+		if field(VERSION)? < 17 {
+			return Err("unsupported version");
+		}
+
+		let header = Self {
+			totalsize: field(TOTALSIZE)?,
+			off_dt_struct: field(OFF_DT_STRUCT)?,
+			off_dt_strings: field(OFF_DT_STRINGS)?,
+			size_dt_struct: field(SIZE_DT_STRUCT)?,
+			size_dt_strings: field(SIZE_DT_STRINGS)?,
+		};
+
+		// We only support the recommended order of the blocks.
+		if field(OFF_MEM_RSVMAP)? > header.off_dt_struct
+			|| header.off_dt_struct + header.size_dt_struct > header.off_dt_strings
+			|| header.strings_end() > header.totalsize
+			|| header.totalsize > fdt.len()
+		{
+			return Err("unsupported layout");
+		}
+
+		Ok(header)
+	}
+
+	fn strings_end(&self) -> usize {
+		self.off_dt_strings + self.size_dt_strings
+	}
+}
+
+const INVALID: &str = "invalid structure block";
+
+/// Returns the offset behind the token at `offset`, skipping its name or value.
+fn skip_token(fdt: &[u8], header: &Header, offset: usize) -> Result<(u32, usize), &'static str> {
+	let token = read_u32(fdt, offset).ok_or(INVALID)?;
+	let next = match token {
+		FDT_BEGIN_NODE => {
+			let name = read_str(fdt, offset + 4).ok_or(INVALID)?;
+			(offset + 4 + name.len() + 1).align_up(4)
+		}
+		FDT_PROP => {
+			let len = read_u32(fdt, offset + 4).ok_or(INVALID)? as usize;
+			(offset + 12 + len).align_up(4)
+		}
+		FDT_END_NODE | FDT_NOP | FDT_END => offset + 4,
+		_ => return Err(INVALID),
+	};
+
+	if next > header.off_dt_struct + header.size_dt_struct {
+		return Err(INVALID);
+	}
+	Ok((token, next))
+}
+
+/// Returns the properties starting at `offset`, which end in front of the first child node or the end of the node.
+fn properties(fdt: &[u8], header: &Header, offset: usize) -> Result<Range<usize>, &'static str> {
+	let mut end = offset;
+	loop {
+		let (token, next) = skip_token(fdt, header, end)?;
+		if token != FDT_PROP && token != FDT_NOP {
+			return Ok(offset..end);
+		}
+		end = next;
+	}
+}
+
+/// Returns the offset of the property `name` in `properties`.
+fn find_property(
+	fdt: &[u8],
+	header: &Header,
+	properties: Range<usize>,
+	name: &str,
+) -> Result<Option<usize>, &'static str> {
+	let mut offset = properties.start;
+	while offset < properties.end {
+		let (token, next) = skip_token(fdt, header, offset)?;
+		if token == FDT_PROP {
+			let name_offset = read_u32(fdt, offset + 8).ok_or(INVALID)? as usize;
+			if read_str(fdt, header.off_dt_strings + name_offset) == Some(name.as_bytes()) {
+				return Ok(Some(offset));
+			}
+		}
+		offset = next;
+	}
+	Ok(None)
+}
+
+/// Returns the value of the property at `offset`.
+fn property_value(fdt: &[u8], offset: usize) -> Option<Range<usize>> {
+	let len = read_u32(fdt, offset + 4)? as usize;
+	let value = offset + 12..offset + 12 + len;
+	(value.end <= fdt.len()).then_some(value)
+}
+
+/// A child node of the root node.
+enum Node {
+	/// The node exists.
+	Found {
+		/// The properties of the node.
+		properties: Range<usize>,
+		/// The offset of the `FDT_END_NODE` token of the node.
+		end: usize,
+	},
+	/// The node does not exist and can be added in front of the end of the root node at the offset.
+	Missing(usize),
+}
+
+/// Returns the properties of the root node.
+fn root_properties(fdt: &[u8], header: &Header) -> Result<Range<usize>, &'static str> {
+	let (token, next) = skip_token(fdt, header, header.off_dt_struct)?;
+	if token != FDT_BEGIN_NODE {
+		return Err(INVALID);
+	}
+	properties(fdt, header, next)
+}
+
+/// Finds the child node `name` of the root node.
+fn find_node(fdt: &[u8], header: &Header, name: &str) -> Result<Node, &'static str> {
+	let mut offset = root_properties(fdt, header)?.end;
+	let mut depth = 1;
+	let mut found = None;
+
+	loop {
+		let (token, next) = skip_token(fdt, header, offset)?;
+		match token {
+			FDT_BEGIN_NODE => {
+				depth += 1;
+				if depth == 2 && read_str(fdt, offset + 4) == Some(name.as_bytes()) {
+					found = Some(properties(fdt, header, next)?);
+				}
+			}
+			FDT_END_NODE => {
+				depth -= 1;
+				match depth {
+					0 => return Ok(Node::Missing(offset)),
+					1 => {
+						if let Some(properties) = found {
+							return Ok(Node::Found {
+								properties,
+								end: offset,
+							});
+						}
+					}
+					_ => {}
+				}
+			}
+			FDT_END => return Err(INVALID),
+			_ => {}
+		}
+		offset = next;
+	}
+}
+
+/// Returns the offset of the property `name` of the child node `node` of the root node.
+fn find_node_property(
+	fdt: &[u8],
+	header: &Header,
+	node: &str,
+	name: &str,
+) -> Result<Option<usize>, &'static str> {
+	match find_node(fdt, header, node)? {
+		Node::Found { properties, .. } => find_property(fdt, header, properties, name),
+		Node::Missing(_) => Ok(None),
+	}
+}
+
+/// Nodes and properties for the structure block with the names that are missing from the strings block.
+struct Structure<'a> {
+	strings: &'a [u8],
+	structure: Vec<u8>,
+	new_strings: Vec<u8>,
+}
+
+impl<'a> Structure<'a> {
+	fn new(fdt: &'a [u8], header: &Header) -> Self {
+		Self {
+			strings: &fdt[header.off_dt_strings..header.strings_end()],
+			structure: Vec::new(),
+			new_strings: Vec::new(),
+		}
+	}
+
+	fn put(&mut self, bytes: &[u8]) {
+		self.structure.extend_from_slice(bytes);
+		self.structure.resize(self.structure.len().align_up(4), 0);
+	}
+
+	fn begin_node(&mut self, name: &str) {
+		self.put(&FDT_BEGIN_NODE.to_be_bytes());
+		let mut name_nul = Vec::with_capacity(name.len() + 1);
+		name_nul.extend_from_slice(name.as_bytes());
+		name_nul.push(0);
+		self.put(&name_nul);
+	}
+
+	fn end_node(&mut self) {
+		self.put(&FDT_END_NODE.to_be_bytes());
+	}
+
+	fn property(&mut self, name: &str, value: &[u8]) {
+		let name_offset = self.name_offset(name);
+		self.put(&FDT_PROP.to_be_bytes());
+		self.put(&u32::try_from(value.len()).unwrap().to_be_bytes());
+		self.put(&u32::try_from(name_offset).unwrap().to_be_bytes());
+		self.put(value);
+	}
+
+	/// Returns the offset of `name` in the strings block, adding it if needed.
+	fn name_offset(&mut self, name: &str) -> usize {
+		let mut name_nul = Vec::with_capacity(name.len() + 1);
+		name_nul.extend_from_slice(name.as_bytes());
+		name_nul.push(0);
+
+		// Reuse the name if it is in the strings block already.
+		let find = |strings: &[u8]| {
+			strings
+				.windows(name_nul.len())
+				.position(|window| window == name_nul)
+		};
+		if let Some(offset) = find(self.strings) {
+			return offset;
+		}
+		if let Some(offset) = find(&self.new_strings) {
+			return self.strings.len() + offset;
+		}
+
+		let offset = self.strings.len() + self.new_strings.len();
+		self.new_strings.extend_from_slice(&name_nul);
+		offset
+	}
+
+	fn finish(self) -> (Vec<u8>, Vec<u8>) {
+		(self.structure, self.new_strings)
+	}
+}
+
+/// Replaces `range` of the structure block with `structure` and appends `strings` to the strings block.
+///
+/// The devicetree is left unchanged if there is not enough space.
+fn splice(
+	fdt: &mut [u8],
+	header: &Header,
+	range: Range<usize>,
+	structure: &[u8],
+	strings: &[u8],
+) -> Result<(), &'static str> {
+	let strings_end = header.strings_end() + structure.len() - range.len();
+	let new_strings_end = strings_end + strings.len();
+	if new_strings_end > fdt.len() {
+		return Err("not enough space");
+	}
+
+	fdt.copy_within(
+		range.end..header.strings_end(),
+		range.start + structure.len(),
+	);
+	fdt[range.start..range.start + structure.len()].copy_from_slice(structure);
+	fdt[strings_end..new_strings_end].copy_from_slice(strings);
+	if new_strings_end < header.strings_end() {
+		fdt[new_strings_end..header.strings_end()].fill(0);
+	}
+
+	let to_u32 = |value: usize| u32::try_from(value).unwrap();
+	write_u32(
+		fdt,
+		OFF_DT_STRINGS,
+		to_u32(header.off_dt_strings + structure.len() - range.len()),
+	);
+	write_u32(
+		fdt,
+		SIZE_DT_STRUCT,
+		to_u32(header.size_dt_struct + structure.len() - range.len()),
+	);
+	write_u32(
+		fdt,
+		SIZE_DT_STRINGS,
+		to_u32(header.size_dt_strings + strings.len()),
+	);
+	write_u32(
+		fdt,
+		TOTALSIZE,
+		to_u32(header.totalsize.max(new_strings_end)),
+	);
+
+	Ok(())
+}
+
+/// Returns the size of the devicetree from its header.
+#[cfg_attr(
+	not(any(target_arch = "aarch64", target_arch = "riscv64")),
+	expect(dead_code)
+)]
+pub fn total_size(fdt: &[u8]) -> Result<usize, &'static str> {
+	Header::parse(fdt).map(|header| header.totalsize)
+}
+
+/// Returns the value of the property `name` of `/chosen` for modifying it in place.
+pub fn chosen_property_mut<'a>(fdt: &'a mut [u8], name: &str) -> Option<&'a mut [u8]> {
+	let header = Header::parse(fdt).ok()?;
+	let offset = find_node_property(fdt, &header, "chosen", name).ok()??;
+	let value = property_value(fdt, offset)?;
+	Some(&mut fdt[value])
+}
+
+/// Sets the property `name` of `/chosen` to `value`, replacing it in place if it exists.
+///
+/// The devicetree is left unchanged if there is not enough space for `value`.
+#[cfg_attr(
+	not(any(target_arch = "aarch64", target_arch = "riscv64")),
+	expect(dead_code)
+)]
+pub fn set_chosen_property(fdt: &mut [u8], name: &str, value: &[u8]) -> Result<(), &'static str> {
+	let header = Header::parse(fdt)?;
+	let Some(offset) = find_node_property(fdt, &header, "chosen", name)? else {
+		return add_chosen_property(fdt, name, value);
+	};
+
+	let (_, end) = skip_token(fdt, &header, offset)?;
+	let mut structure = Structure::new(fdt, &header);
+	structure.property(name, value);
+	let (structure, strings) = structure.finish();
+	splice(fdt, &header, offset..end, &structure, &strings)
+}
+
+/// Adds the property `name` with `value` to `/chosen`, which is created if needed.
+pub fn add_chosen_property(fdt: &mut [u8], name: &str, value: &[u8]) -> Result<(), &'static str> {
+	let header = Header::parse(fdt)?;
+	let mut structure = Structure::new(fdt, &header);
+	let offset = match find_node(fdt, &header, "chosen")? {
+		Node::Found { properties, .. } => {
+			if find_property(fdt, &header, properties.clone(), name)?.is_some() {
+				return Err("property exists already");
+			}
+			structure.property(name, value);
+			properties.end
+		}
+		Node::Missing(offset) => {
+			structure.begin_node("chosen");
+			structure.property(name, value);
+			structure.end_node();
+			offset
+		}
+	};
+
+	let (structure, strings) = structure.finish();
+	splice(fdt, &header, offset..offset, &structure, &strings)
+}
+
+/// Adds the node `name` for `region` to `/reserved-memory`, which is created if needed.
+///
+/// The node contains `compatible` and `reg` and is added behind the existing reserved memory regions.
+#[cfg_attr(
+	not(any(target_arch = "aarch64", target_arch = "riscv64")),
+	expect(dead_code)
+)]
+pub fn add_reserved_memory(
+	fdt: &mut [u8],
+	name: &str,
+	compatible: &str,
+	region: Range<u64>,
+) -> Result<(), &'static str> {
+	let header = Header::parse(fdt)?;
+	let mut structure = Structure::new(fdt, &header);
+	let (offset, cells, create) = match find_node(fdt, &header, "reserved-memory")? {
+		Node::Found { properties, end } => {
+			(end, address_size_cells(fdt, &header, properties)?, false)
+		}
+		// `/reserved-memory` uses the same address space as the root node.
+		Node::Missing(offset) => (
+			offset,
+			address_size_cells(fdt, &header, root_properties(fdt, &header)?)?,
+			true,
+		),
+	};
+
+	let (address_cells, size_cells) = cells;
+	let mut reg = Vec::new();
+	encode_cells(&mut reg, region.start, address_cells)?;
+	encode_cells(&mut reg, region.end - region.start, size_cells)?;
+	let mut compatible_nul = Vec::with_capacity(compatible.len() + 1);
+	compatible_nul.extend_from_slice(compatible.as_bytes());
+	compatible_nul.push(0);
+
+	if create {
+		structure.begin_node("reserved-memory");
+		structure.property("#address-cells", &address_cells.to_be_bytes());
+		structure.property("#size-cells", &size_cells.to_be_bytes());
+		structure.property("ranges", &[]);
+	}
+	structure.begin_node(name);
+	structure.property("compatible", &compatible_nul);
+	structure.property("reg", &reg);
+	structure.end_node();
+	if create {
+		structure.end_node();
+	}
+
+	let (structure, strings) = structure.finish();
+	splice(fdt, &header, offset..offset, &structure, &strings)
+}
+
+/// Returns `#address-cells` and `#size-cells` from `properties`.
+fn address_size_cells(
+	fdt: &[u8],
+	header: &Header,
+	properties: Range<usize>,
+) -> Result<(u32, u32), &'static str> {
+	let cells = |name, default| -> Result<u32, &'static str> {
+		let Some(offset) = find_property(fdt, header, properties.clone(), name)? else {
+			return Ok(default);
+		};
+		let value = property_value(fdt, offset).ok_or(INVALID)?;
+		let value = fdt[value].try_into().map_err(|_| INVALID)?;
+		Ok(u32::from_be_bytes(value))
+	};
+
+	// These are the defaults from the specification.
+	Ok((cells("#address-cells", 2)?, cells("#size-cells", 1)?))
+}
+
+/// Appends `value` as `cells` big-endian 32-bit cells to `bytes`.
+fn encode_cells(bytes: &mut Vec<u8>, value: u64, cells: u32) -> Result<(), &'static str> {
+	match cells {
+		1 => {
+			let value = u32::try_from(value).map_err(|_| "value does not fit into the cells")?;
+			bytes.extend_from_slice(&value.to_be_bytes());
+		}
+		2 => bytes.extend_from_slice(&value.to_be_bytes()),
+		_ => return Err("unsupported number of cells"),
+	}
+	Ok(())
+}
+
+#[cfg(all(test, not(target_os = "none")))]
+mod tests {
+	use alloc::format;
+	use alloc::string::String;
+
+	use vm_fdt::FdtWriter;
+
+	use super::*;
+
+	/// Returns a devicetree with `/chosen` and free space behind it.
+	///
+	/// `/chosen` contains `bootargs` and, if `module` is set, a `module@` child node.
+	fn fdt(chosen: bool, module: bool) -> Vec<u8> {
+		let mut writer = FdtWriter::new().unwrap();
+		let root = writer.begin_node("").unwrap();
+		writer.property_u32("#address-cells", 2).unwrap();
+		if chosen {
+			let chosen = writer.begin_node("chosen").unwrap();
+			writer.property_string("bootargs", "-freq 42").unwrap();
+			if module {
+				let module = writer.begin_node("module@48000000").unwrap();
+				writer
+					.property_string("compatible", "multiboot,kernel")
+					.unwrap();
+				writer.end_node(module).unwrap();
+			}
+			writer.end_node(chosen).unwrap();
+		}
+		let memory = writer.begin_node("memory@0").unwrap();
+		writer.property_string("device_type", "memory").unwrap();
+		writer.end_node(memory).unwrap();
+		writer.end_node(root).unwrap();
+
+		let mut fdt = writer.finish().unwrap();
+		fdt.resize(fdt.len() + 0x100, 0xff);
+		fdt
+	}
+
+	/// Returns the properties and nodes of the structure block as `prop:<path>/<name>` and `node:<path>`.
+	fn structure(fdt: &[u8]) -> Vec<String> {
+		let header = Header::parse(fdt).unwrap();
+		let mut items = Vec::new();
+		let mut path = Vec::new();
+		let mut offset = header.off_dt_struct;
+		loop {
+			let token = read_u32(fdt, offset).unwrap();
+			offset += 4;
+			match token {
+				FDT_BEGIN_NODE => {
+					let name = read_str(fdt, offset).unwrap();
+					offset = (offset + name.len() + 1).align_up(4);
+					path.push(String::from_utf8(name.to_vec()).unwrap());
+					items.push(format!("node:{}", path.join("/")));
+				}
+				FDT_END_NODE => {
+					path.pop().unwrap();
+				}
+				FDT_PROP => {
+					let len = read_u32(fdt, offset).unwrap() as usize;
+					let name_offset = read_u32(fdt, offset + 4).unwrap() as usize;
+					offset = (offset + 8 + len).align_up(4);
+					let name = read_str(fdt, header.off_dt_strings + name_offset).unwrap();
+					let name = String::from_utf8(name.to_vec()).unwrap();
+					items.push(format!("prop:{}/{name}", path.join("/")));
+				}
+				FDT_NOP => {}
+				FDT_END => break,
+				_ => panic!("invalid token {token:#x}"),
+			}
+		}
+		assert!(path.is_empty());
+		assert_eq!(offset, header.off_dt_struct + header.size_dt_struct);
+		items
+	}
+
+	/// Returns the value of the property `name` of `/chosen`.
+	fn chosen_property(fdt: &[u8], name: &str) -> Option<Vec<u8>> {
+		let mut fdt = fdt.to_vec();
+		chosen_property_mut(&mut fdt, name).map(|value| value.to_vec())
+	}
+
+	#[test]
+	fn add_property() {
+		let mut fdt = fdt(true, false);
+		add_chosen_property(&mut fdt, "rng-seed", &[1, 2, 3]).unwrap();
+
+		assert_eq!(
+			structure(&fdt),
+			[
+				"node:",
+				"prop:/#address-cells",
+				"node:/chosen",
+				"prop:/chosen/bootargs",
+				"prop:/chosen/rng-seed",
+				"node:/memory@0",
+				"prop:/memory@0/device_type",
+			]
+		);
+		assert_eq!(chosen_property(&fdt, "rng-seed").unwrap(), [1, 2, 3]);
+		assert_eq!(chosen_property(&fdt, "bootargs").unwrap(), b"-freq 42\0");
+	}
+
+	#[test]
+	fn add_property_before_child_nodes() {
+		let mut fdt = fdt(true, true);
+		add_chosen_property(&mut fdt, "rng-seed", &[1, 2, 3]).unwrap();
+		add_chosen_property(&mut fdt, "hermit,env", b"A=B\0").unwrap();
+
+		assert_eq!(
+			structure(&fdt),
+			[
+				"node:",
+				"prop:/#address-cells",
+				"node:/chosen",
+				"prop:/chosen/bootargs",
+				"prop:/chosen/rng-seed",
+				"prop:/chosen/hermit,env",
+				"node:/chosen/module@48000000",
+				"prop:/chosen/module@48000000/compatible",
+				"node:/memory@0",
+				"prop:/memory@0/device_type",
+			]
+		);
+		assert_eq!(chosen_property(&fdt, "rng-seed").unwrap(), [1, 2, 3]);
+		assert_eq!(chosen_property(&fdt, "hermit,env").unwrap(), b"A=B\0");
+	}
+
+	#[test]
+	fn add_node() {
+		let mut fdt = fdt(false, false);
+		add_chosen_property(&mut fdt, "rng-seed", &[1, 2, 3]).unwrap();
+
+		assert_eq!(
+			structure(&fdt),
+			[
+				"node:",
+				"prop:/#address-cells",
+				"node:/memory@0",
+				"prop:/memory@0/device_type",
+				"node:/chosen",
+				"prop:/chosen/rng-seed",
+			]
+		);
+		assert_eq!(chosen_property(&fdt, "rng-seed").unwrap(), [1, 2, 3]);
+	}
+
+	#[test]
+	fn add_existing_property() {
+		let mut fdt = fdt(true, true);
+		let original = fdt.clone();
+
+		assert!(add_chosen_property(&mut fdt, "bootargs", b"\0").is_err());
+		assert_eq!(fdt, original);
+	}
+
+	#[test]
+	fn add_property_without_space() {
+		let mut fdt = fdt(true, true);
+		let totalsize = Header::parse(&fdt).unwrap().totalsize;
+		fdt.truncate(totalsize + 8);
+		let original = fdt.clone();
+
+		assert!(add_chosen_property(&mut fdt, "rng-seed", &[1, 2, 3]).is_err());
+		assert_eq!(fdt, original);
+	}
+
+	#[test]
+	fn set_property() {
+		for bootargs in [&b"\0"[..], b"-freq 42 -- app\0"] {
+			let mut fdt = fdt(true, true);
+			let original = structure(&fdt);
+			set_chosen_property(&mut fdt, "bootargs", bootargs).unwrap();
+
+			assert_eq!(structure(&fdt), original);
+			assert_eq!(chosen_property(&fdt, "bootargs").unwrap(), bootargs);
+		}
+	}
+
+	#[test]
+	fn set_missing_property() {
+		let mut fdt = fdt(false, false);
+		set_chosen_property(&mut fdt, "bootargs", b"-freq 42\0").unwrap();
+
+		assert_eq!(chosen_property(&fdt, "bootargs").unwrap(), b"-freq 42\0");
+	}
+
+	#[test]
+	fn set_property_without_space() {
+		let mut fdt = fdt(true, true);
+		let totalsize = Header::parse(&fdt).unwrap().totalsize;
+		fdt.truncate(totalsize + 8);
+		let original = fdt.clone();
+
+		assert!(set_chosen_property(&mut fdt, "bootargs", &[b'a'; 32]).is_err());
+		assert_eq!(fdt, original);
+	}
+
+	#[test]
+	fn modify_property() {
+		let mut fdt = fdt(true, true);
+		chosen_property_mut(&mut fdt, "bootargs")
+			.unwrap()
+			.copy_from_slice(b"-freq 43\0");
+
+		assert_eq!(chosen_property(&fdt, "bootargs").unwrap(), b"-freq 43\0");
+		assert!(chosen_property_mut(&mut fdt, "rng-seed").is_none());
+	}
+
+	/// Returns the value of the property `name` of the node `child` of `/<parent>`.
+	fn child_property(fdt: &[u8], parent: &str, child: &str, name: &str) -> Option<Vec<u8>> {
+		let header = Header::parse(fdt).unwrap();
+		let Node::Found {
+			properties: parent_properties,
+			end,
+		} = find_node(fdt, &header, parent).unwrap()
+		else {
+			return None;
+		};
+
+		let mut offset = parent_properties.end;
+		while offset < end {
+			let (token, next) = skip_token(fdt, &header, offset).unwrap();
+			if token == FDT_BEGIN_NODE && read_str(fdt, offset + 4) == Some(child.as_bytes()) {
+				let properties = properties(fdt, &header, next).unwrap();
+				let offset = find_property(fdt, &header, properties, name).unwrap()?;
+				return Some(fdt[property_value(fdt, offset).unwrap()].to_vec());
+			}
+			offset = next;
+		}
+		None
+	}
+
+	#[test]
+	fn add_reserved_memory_node() {
+		let mut fdt = fdt(true, true);
+		add_reserved_memory(&mut fdt, "log@1000", "hermit,log", 0x1000..0x3000).unwrap();
+
+		assert_eq!(
+			structure(&fdt)[6..],
+			[
+				"node:/memory@0",
+				"prop:/memory@0/device_type",
+				"node:/reserved-memory",
+				"prop:/reserved-memory/#address-cells",
+				"prop:/reserved-memory/#size-cells",
+				"prop:/reserved-memory/ranges",
+				"node:/reserved-memory/log@1000",
+				"prop:/reserved-memory/log@1000/compatible",
+				"prop:/reserved-memory/log@1000/reg",
+			]
+		);
+		// The root node has 2 address cells and the default of 1 size cell.
+		assert_eq!(
+			child_property(&fdt, "reserved-memory", "log@1000", "reg").unwrap(),
+			[0, 0, 0, 0, 0, 0, 0x10, 0, 0, 0, 0x20, 0]
+		);
+		assert_eq!(
+			child_property(&fdt, "reserved-memory", "log@1000", "compatible").unwrap(),
+			b"hermit,log\0"
+		);
+	}
+
+	#[test]
+	fn add_reserved_memory_region() {
+		let mut writer = FdtWriter::new().unwrap();
+		let root = writer.begin_node("").unwrap();
+		let reserved_memory = writer.begin_node("reserved-memory").unwrap();
+		writer.property_u32("#address-cells", 1).unwrap();
+		writer.property_u32("#size-cells", 1).unwrap();
+		writer.property_null("ranges").unwrap();
+		let firmware = writer.begin_node("firmware@0").unwrap();
+		writer.property_array_u32("reg", &[0, 0x1000]).unwrap();
+		writer.end_node(firmware).unwrap();
+		writer.end_node(reserved_memory).unwrap();
+		writer.end_node(root).unwrap();
+		let mut fdt = writer.finish().unwrap();
+		fdt.resize(fdt.len() + 0x100, 0xff);
+
+		add_reserved_memory(&mut fdt, "log@1000", "hermit,log", 0x1000..0x3000).unwrap();
+
+		assert_eq!(
+			structure(&fdt),
+			[
+				"node:",
+				"node:/reserved-memory",
+				"prop:/reserved-memory/#address-cells",
+				"prop:/reserved-memory/#size-cells",
+				"prop:/reserved-memory/ranges",
+				"node:/reserved-memory/firmware@0",
+				"prop:/reserved-memory/firmware@0/reg",
+				"node:/reserved-memory/log@1000",
+				"prop:/reserved-memory/log@1000/compatible",
+				"prop:/reserved-memory/log@1000/reg",
+			]
+		);
+		assert_eq!(
+			child_property(&fdt, "reserved-memory", "log@1000", "reg").unwrap(),
+			[0, 0, 0x10, 0, 0, 0, 0x20, 0]
+		);
+		assert!(add_reserved_memory(&mut fdt, "log@0", "hermit,log", 0..0x1_0000_0000).is_err());
+	}
+}
@@ -0,0 +1,164 @@
+//! Modifications of the devicetree from the firmware.
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use log::{info, warn};
+
+use crate::boot_time::{self, Phase};
+use crate::fdt_edit::{self, add_chosen_property, chosen_property_mut, set_chosen_property};
+use crate::measured_boot::{self, Measurement};
+use crate::{arch, cmdline, entropy, log_buffer};
+
+/// Returns `/chosen/hermit,env` for the environment variables from `cmdline` (see [`cmdline::env`]).
+///
+/// The command line may be part of the devicetree, so this has to be called before [`patch`].
+pub fn env(cmdline: Option<&str>) -> Vec<u8> {
+	let mut env = Vec::new();
+	for var in cmdline.into_iter().flat_map(cmdline::env) {
+		env.extend_from_slice(var.as_bytes());
+		env.push(0);
+	}
+	env
+}
+
+/// Adds the loader's properties to the devicetree and measures it.
+///
+/// `fdt` starts with the devicetree and may contain free space behind it, into which the devicetree is grown.
+/// `env` is the value of `/chosen/hermit,env` from [`env`], which is left out if empty.
+pub fn patch(fdt: &mut [u8], env: &[u8]) {
+	set_bootargs(fdt);
+	add_env(fdt, env);
+	clear_kaslr_seed(fdt);
+	set_rng_seed(fdt);
+	add_digests(fdt);
+	add_log_buffer(fdt);
+	add_boot_timestamps(fdt);
+
+	match fdt_edit::total_size(fdt) {
+		Ok(len) => measured_boot::measure(Measurement::Fdt, &fdt[..len]),
+		Err(err) => warn!("Could not measure the devicetree: {err}"),
+	}
+}
+
+/// Replaces `/chosen/bootargs` with the command line edited at the prompt.
+fn set_bootargs(fdt: &mut [u8]) {
+	let Some(cmdline) = cmdline::edited() else {
+		return;
+	};
+
+	let mut bootargs = Vec::with_capacity(cmdline.len() + 1);
+	bootargs.extend_from_slice(cmdline.as_bytes());
+	bootargs.push(0);
+
+	match set_chosen_property(fdt, "bootargs", &bootargs) {
+		Ok(()) => info!("Replaced /chosen/bootargs with the edited command line"),
+		Err(err) => warn!("Could not replace /chosen/bootargs in the devicetree: {err}"),
+	}
+}
+
+/// Adds the environment variables of the application to `/chosen/hermit,env`.
+fn add_env(fdt: &mut [u8], env: &[u8]) {
+	if env.is_empty() {
+		return;
+	}
+
+	if let Err(err) = add_chosen_property(fdt, "hermit,env", env) {
+		warn!("Could not add /chosen/hermit,env to the devicetree: {err}");
+	}
+}
+
+/// Zeroes `/chosen/kaslr-seed`, so that the kernel cannot recompute its address.
+fn clear_kaslr_seed(fdt: &mut [u8]) {
+	if let Some(kaslr_seed) = chosen_property_mut(fdt, "kaslr-seed") {
+		kaslr_seed.fill(0);
+		info!("Cleared /chosen/kaslr-seed");
+	}
+}
+
+/// Sets `/chosen/rng-seed` to a new seed for the kernel's random number generator.
+///
+/// The seed from the firmware may have selected the kernel address, so it is stirred instead of passed on.
+fn set_rng_seed(fdt: &mut [u8]) {
+	let seed = match chosen_property_mut(fdt, "rng-seed") {
+		Some(firmware_seed) => Some(entropy::stir(firmware_seed)),
+		None => entropy::seed(),
+	};
+	let Some(seed) = seed else {
+		warn!("Could not find an entropy source for /chosen/rng-seed");
+		return;
+	};
+
+	match set_chosen_property(fdt, "rng-seed", &seed) {
+		Ok(()) => info!("Set /chosen/rng-seed in the devicetree"),
+		Err(err) => {
+			warn!("Could not set /chosen/rng-seed in the devicetree: {err}");
+			if let Some(firmware_seed) = chosen_property_mut(fdt, "rng-seed") {
+				firmware_seed.fill(0);
+			}
+		}
+	}
+}
+
+/// Adds the digests of the application image, the command line, and the environment variables to `/chosen`.
+fn add_digests(fdt: &mut [u8]) {
+	let digests = [
+		("hermit,app-sha256", measured_boot::image()),
+		("hermit,cmdline-sha256", measured_boot::cmdline()),
+		("hermit,env-sha256", measured_boot::env()),
+	];
+
+	for (name, digest) in digests {
+		let Some(digest) = digest else {
+			continue;
+		};
+		if let Err(err) = add_chosen_property(fdt, name, &digest) {
+			warn!("Could not add /chosen/{name} to the devicetree: {err}");
+		}
+	}
+}
+
+/// Adds the log buffer (see [`log_buffer`]) to `/reserved-memory`.
+fn add_log_buffer(fdt: &mut [u8]) {
+	// The loader log is recorded until the kernel is entered and has to be kept by the kernel.
+	let log_buffer = log_buffer::region();
+	let name = format!("loader-log@{:x}", log_buffer.start);
+	if let Err(err) = fdt_edit::add_reserved_memory(fdt, &name, "hermit,loader-log", log_buffer) {
+		warn!("Could not add /reserved-memory/{name} to the devicetree: {err}");
+	}
+}
+
+/// Adds the boot timestamps (see [`boot_time`]) to `/chosen`.
+///
+/// The timestamp for entering the kernel is filled in by [`boot_time::finish`].
+fn add_boot_timestamps(fdt: &mut [u8]) {
+	boot_time::record(Phase::Fdt);
+
+	let mut timestamps = Vec::new();
+	for timestamp in boot_time::timestamps() {
+		timestamps.extend_from_slice(&timestamp.to_be_bytes());
+	}
+	let mut names = Vec::new();
+	for name in boot_time::names() {
+		names.extend_from_slice(name.as_bytes());
+		names.push(0);
+	}
+	let frequency = arch::timestamp_frequency().map(u64::to_be_bytes);
+
+	let properties = [
+		("hermit,boot-timestamps", Some(&timestamps[..])),
+		("hermit,boot-timestamp-names", Some(&names[..])),
+		(
+			"hermit,boot-timestamp-frequency",
+			frequency.as_ref().map(|frequency| &frequency[..]),
+		),
+	];
+	for (name, value) in properties {
+		let Some(value) = value else {
+			continue;
+		};
+		if let Err(err) = add_chosen_property(fdt, name, value) {
+			warn!("Could not add /chosen/{name} to the devicetree: {err}");
+		}
+	}
+}
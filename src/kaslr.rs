@@ -0,0 +1,217 @@
+//! Kernel address space layout randomization (KASLR).
+//!
+//! Relocatable kernels are loaded to a random 2 MiB-aligned address in usable RAM.
+//! The `nokaslr` kernel argument disables this.
+
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use align_address::Align;
+use log::{info, warn};
+
+use crate::cmdline;
+
+/// The alignment of random kernel addresses.
+const ALIGN: u64 = 0x20_0000;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Disables KASLR if `cmdline` contains `nokaslr`.
+pub fn init(cmdline: &str) {
+	if cmdline::flag(cmdline, "nokaslr") {
+		ENABLED.store(false, Ordering::Relaxed);
+	}
+}
+
+/// Returns a random address for a relocatable kernel of `size` bytes.
+///
+/// The kernel is placed in the `usable` memory regions without overlapping the `reserved` ones.
+/// `seed` is only called if KASLR is enabled.
+/// Returns `None` if KASLR is disabled, no random seed is available, or the kernel does not fit anywhere.
+pub fn choose<U, R>(
+	usable: impl Fn() -> U,
+	reserved: impl Fn() -> R,
+	size: u64,
+	seed: impl FnOnce() -> Option<u64>,
+) -> Option<u64>
+where
+	U: Iterator<Item = Range<u64>>,
+	R: Iterator<Item = Range<u64>>,
+{
+	if !ENABLED.load(Ordering::Relaxed) {
+		info!("KASLR is disabled");
+		return None;
+	}
+
+	let Some(seed) = seed() else {
+		warn!("Could not find a random seed, disabling KASLR");
+		return None;
+	};
+
+	choose_with_seed(usable, reserved, size, seed)
+}
+
+/// Returns the address of the kernel slot that is selected by `seed` (see [`choose`]).
+fn choose_with_seed<U, R>(
+	usable: impl Fn() -> U,
+	reserved: impl Fn() -> R,
+	size: u64,
+	seed: u64,
+) -> Option<u64>
+where
+	U: Iterator<Item = Range<u64>>,
+	R: Iterator<Item = Range<u64>>,
+{
+	let slots = || {
+		usable()
+			.flat_map(|region| {
+				// Never place the kernel at address 0.
+				let start = region.start.max(ALIGN).align_up(ALIGN);
+				let end = region.end.saturating_sub(size);
+				(start..=end).step_by(ALIGN as usize)
+			})
+			.filter(|&start| {
+				let end = start + size;
+				!reserved().any(|reserved| start < reserved.end && reserved.start < end)
+			})
+	};
+
+	let count = slots().count();
+	if count == 0 {
+		warn!("Could not find a slot for the kernel (size = {size:#x}), disabling KASLR");
+		return None;
+	}
+
+	let index = (seed % count as u64) as usize;
+	let addr = slots().nth(index).unwrap();
+	info!("KASLR: Loading kernel to slot {index} of {count} at {addr:#x}");
+	Some(addr)
+}
+
+/// Returns a seed from the devicetree properties `/chosen/kaslr-seed` and `/chosen/rng-seed`.
+///
+/// Zero seeds are ignored, since they have been consumed already.
+#[cfg_attr(target_arch = "x86_64", expect(dead_code))]
+pub fn fdt_seed(kaslr_seed: Option<&[u8]>, rng_seed: Option<&[u8]>) -> Option<u64> {
+	let kaslr_seed = kaslr_seed
+		.and_then(|kaslr_seed| kaslr_seed.try_into().ok())
+		.map(u64::from_be_bytes);
+
+	let rng_seed = rng_seed.map(|rng_seed| {
+		rng_seed.chunks(8).fold(0, |seed, chunk| {
+			let mut bytes = [0; 8];
+			bytes[..chunk.len()].copy_from_slice(chunk);
+			seed ^ u64::from_le_bytes(bytes)
+		})
+	});
+
+	kaslr_seed
+		.into_iter()
+		.chain(rng_seed)
+		.find(|&seed| seed != 0)
+}
+
+#[cfg(all(test, not(target_os = "none")))]
+// Single memory regions are meant as slices of ranges.
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+	use alloc::vec::Vec;
+	use core::iter;
+
+	use super::*;
+
+	const MIB: u64 = 0x10_0000;
+
+	/// Returns the addresses of all slots.
+	fn slots(usable: &[Range<u64>], reserved: &[Range<u64>], size: u64) -> Vec<u64> {
+		let choose = |seed| {
+			choose_with_seed(
+				|| usable.iter().cloned(),
+				|| reserved.iter().cloned(),
+				size,
+				seed,
+			)
+		};
+
+		let Some(first) = choose(0) else {
+			return Vec::new();
+		};
+		let mut slots = Vec::from([first]);
+		for seed in 1.. {
+			let slot = choose(seed).unwrap();
+			if slot == first {
+				break;
+			}
+			slots.push(slot);
+		}
+		slots
+	}
+
+	#[test]
+	fn choose_aligned_slots() {
+		assert_eq!(
+			slots(&[MIB..9 * MIB], &[], 3 * MIB),
+			[2 * MIB, 4 * MIB, 6 * MIB]
+		);
+	}
+
+	#[test]
+	fn choose_slots_without_zero() {
+		assert_eq!(slots(&[0..6 * MIB], &[], 2 * MIB), [2 * MIB, 4 * MIB]);
+	}
+
+	#[test]
+	fn choose_slots_outside_reserved_regions() {
+		assert_eq!(
+			slots(
+				&[0..8 * MIB, 16 * MIB..20 * MIB],
+				&[3 * MIB..3 * MIB + 1, 19 * MIB..21 * MIB],
+				2 * MIB
+			),
+			[4 * MIB, 6 * MIB, 16 * MIB]
+		);
+	}
+
+	#[test]
+	fn choose_without_slots() {
+		assert_eq!(
+			choose_with_seed(|| iter::once(0..4 * MIB), iter::empty, 4 * MIB, 0),
+			None
+		);
+		assert_eq!(
+			choose_with_seed(|| iter::once(0..8 * MIB), || iter::once(0..8 * MIB), MIB, 0),
+			None
+		);
+	}
+
+	#[test]
+	fn fdt_seed_prefers_kaslr_seed() {
+		let kaslr_seed = 0x0102_0304_0506_0708_u64.to_be_bytes();
+		assert_eq!(
+			fdt_seed(Some(&kaslr_seed), Some(&[1; 8])),
+			Some(0x0102_0304_0506_0708)
+		);
+	}
+
+	#[test]
+	fn fdt_seed_folds_rng_seed() {
+		let mut rng_seed = [0; 20];
+		rng_seed[0] = 0x01;
+		rng_seed[8] = 0x03;
+		rng_seed[16] = 0x10;
+		rng_seed[19] = 0x20;
+		assert_eq!(fdt_seed(None, Some(&rng_seed)), Some(0x2000_0012));
+	}
+
+	#[test]
+	fn fdt_seed_ignores_consumed_seeds() {
+		assert_eq!(fdt_seed(Some(&[0; 8]), Some(&[1])), Some(1));
+		assert_eq!(fdt_seed(Some(&[0; 8]), Some(&[0; 32])), None);
+		assert_eq!(fdt_seed(None, None), None);
+	}
+
+	#[test]
+	fn fdt_seed_ignores_invalid_kaslr_seed() {
+		assert_eq!(fdt_seed(Some(&[1; 4]), None), None);
+	}
+}
@@ -3,34 +3,119 @@ use core::fmt;
 use anstyle::AnsiColor;
 use log::{Level, LevelFilter, Metadata, Record};
 
-struct Logger;
+use crate::cmdline;
+
+struct Logger {
+	/// The filter directives from `loader.log=`.
+	directives: Option<&'static str>,
+	default_level: LevelFilter,
+	color: bool,
+}
+
+impl Logger {
+	fn new(cmdline: Option<&'static str>) -> Self {
+		let default_level = option_env!("LOADER_LOG")
+			.map(|var| var.parse().unwrap())
+			.unwrap_or(LevelFilter::Info);
+		let directives = cmdline.and_then(|cmdline| cmdline::values(cmdline, "loader.log").last());
+		let color =
+			match cmdline.and_then(|cmdline| cmdline::values(cmdline, "loader.color").last()) {
+				Some("on") => true,
+				Some("off") => false,
+				_ => !no_color(),
+			};
+
+		Self {
+			directives,
+			default_level,
+			color,
+		}
+	}
+
+	/// Returns the filter directives, which are `<level>` or `<module>=<level>`.
+	fn directives(&self) -> impl Iterator<Item = (Option<&'static str>, LevelFilter)> {
+		self.directives
+			.into_iter()
+			.flat_map(|directives| directives.split(','))
+			.filter_map(|directive| match directive.split_once('=') {
+				Some((module, level)) => Some((Some(module), level.parse().ok()?)),
+				None => Some((None, directive.parse().ok()?)),
+			})
+	}
+
+	/// Returns the level filter for `target`.
+	///
+	/// The most specific matching module directive takes precedence.
+	/// The last directive without a module is the default.
+	fn level(&self, target: &str) -> LevelFilter {
+		let mut default_level = self.default_level;
+		let mut module_level = None;
+		for (module, level) in self.directives() {
+			match module {
+				None => default_level = level,
+				Some(module) if matches_module(target, module) => {
+					if module_level.is_none_or(|(len, _)| module.len() >= len) {
+						module_level = Some((module.len(), level));
+					}
+				}
+				Some(_) => {}
+			}
+		}
+
+		module_level.map_or(default_level, |(_, level)| level)
+	}
+
+	/// Returns the most verbose level that may be enabled.
+	fn max_level(&self) -> LevelFilter {
+		self.directives()
+			.map(|(_, level)| level)
+			.fold(self.default_level, Ord::max)
+	}
+}
+
+/// Returns whether `module` is a module in the module path `target`.
+///
+/// `module` may be any part of the path, like `paging` or `x86_64::paging` for `hermit_loader::arch::x86_64::paging`.
+fn matches_module(target: &str, module: &str) -> bool {
+	target.match_indices(module).any(|(index, _)| {
+		let before = &target[..index];
+		let after = &target[index + module.len()..];
+		(before.is_empty() || before.ends_with("::"))
+			&& (after.is_empty() || after.starts_with("::"))
+	})
+}
 
 impl log::Log for Logger {
 	fn enabled(&self, metadata: &Metadata<'_>) -> bool {
-		let level = option_env!("LOADER_LOG")
-			.map(|var| var.parse().unwrap())
-			.unwrap_or(Level::Info);
-		metadata.level() <= level
+		metadata.level() <= self.level(metadata.target())
 	}
 
 	fn log(&self, record: &Record<'_>) {
 		if self.enabled(record.metadata()) {
-			let level = ColorLevel(record.level());
+			let level = ColorLevel {
+				level: record.level(),
+				color: self.color,
+			};
 			let args = record.args();
 			println!("[LOADER][{level}] {args}");
+
+			crate::log_buffer::write_fmt(format_args!("[LOADER][{}] {args}\n", record.level()));
 		}
 	}
 
 	fn flush(&self) {}
 }
 
-struct ColorLevel(Level);
+struct ColorLevel {
+	level: Level,
+	color: bool,
+}
 
 impl fmt::Display for ColorLevel {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		let level = self.0;
+		let level = self.level;
 
-		if no_color() {
+		if !self.color {
 			write!(f, "{level}")
 		} else {
 			let color = match level {
@@ -51,11 +136,15 @@ fn no_color() -> bool {
 	option_env!("NO_COLOR").is_some_and(|val| !val.is_empty())
 }
 
-pub fn init() {
-	static LOGGER: Logger = Logger;
-	log::set_logger(&LOGGER).unwrap();
-	let level_filter = option_env!("LOADER_LOG")
-		.map(|var| var.parse().unwrap())
-		.unwrap_or(LevelFilter::Info);
-	log::set_max_level(level_filter);
+/// Initializes the logger.
+///
+/// `loader.log=` and `loader.color=` in `cmdline` override `LOADER_LOG` and `NO_COLOR` at build time.
+pub fn init(cmdline: Option<&'static str>) {
+	take_static::take_static! {
+		static LOGGER: Option<Logger> = None;
+	}
+
+	let logger = LOGGER.take().unwrap().insert(Logger::new(cmdline));
+	log::set_max_level(logger.max_level());
+	log::set_logger(logger).unwrap();
 }
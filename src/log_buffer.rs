@@ -0,0 +1,65 @@
+//! A ring buffer in memory that records the log for the kernel.
+//!
+//! The buffer is described in the devicetree as `/reserved-memory/loader-log@<addr>` (compatible with `hermit,loader-log`).
+//! It starts with the magic `b"HERMITLG"`, followed by the capacity of the data in bytes and the total number of bytes written as native-endian `u64`s, followed by the data.
+//! Once more bytes have been written than fit into the buffer, the oldest bytes are overwritten and the log starts at `written % capacity`.
+
+use core::ops::Range;
+use core::{fmt, ptr};
+
+use one_shot_mutex::OneShotMutex;
+use sptr::Strict;
+
+const MAGIC: [u8; 8] = *b"HERMITLG";
+const CAPACITY: usize = 0x8000;
+
+#[repr(C)]
+struct LogBuffer {
+	magic: [u8; 8],
+	capacity: u64,
+	written: u64,
+	data: [u8; CAPACITY],
+}
+
+impl LogBuffer {
+	/// Writes the header.
+	///
+	/// The header is not part of the initializer to keep the buffer in `.bss`.
+	fn init(&mut self) {
+		self.magic = MAGIC;
+		self.capacity = CAPACITY as u64;
+	}
+}
+
+impl fmt::Write for LogBuffer {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for byte in s.bytes() {
+			self.data[self.written as usize % CAPACITY] = byte;
+			self.written += 1;
+		}
+		Ok(())
+	}
+}
+
+static LOG_BUFFER: OneShotMutex<LogBuffer> = OneShotMutex::new(LogBuffer {
+	magic: [0; 8],
+	capacity: 0,
+	written: 0,
+	data: [0; CAPACITY],
+});
+
+pub fn write_fmt(args: fmt::Arguments<'_>) {
+	use fmt::Write;
+
+	let mut log_buffer = LOG_BUFFER.lock();
+	log_buffer.init();
+	log_buffer.write_fmt(args).unwrap();
+}
+
+/// Returns the physical memory region of the log buffer.
+pub fn region() -> Range<u64> {
+	let mut log_buffer = LOG_BUFFER.lock();
+	log_buffer.init();
+	let start = u64::try_from(ptr::from_ref(&*log_buffer).expose_addr()).unwrap();
+	start..start + size_of::<LogBuffer>() as u64
+}
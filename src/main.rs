@@ -1,47 +1,74 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![warn(rust_2018_idioms)]
 #![warn(unsafe_op_in_unsafe_fn)]
 #![allow(unstable_name_collisions)]
 #![allow(clippy::missing_safety_doc)]
+// Host tests only build the modules without target dependencies, which leaves some of their items unused.
+#![cfg_attr(test, allow(dead_code, unfulfilled_lint_expectations))]
 
-use ::log::info;
-use hermit_entry::boot_info::{BootInfo, RawBootInfo};
+// These modules do not depend on the target and are tested on the host.
+mod cmdline;
+#[cfg(any(target_os = "uefi", test))]
+mod config;
+mod fdt_edit;
+mod kaslr;
 
+#[cfg(not(test))]
 #[macro_use]
 mod macros;
 
-mod arch;
-mod bump_allocator;
-#[cfg(any(target_os = "uefi", target_arch = "x86_64"))]
-mod fdt;
-mod log;
-mod os;
-
-#[cfg(any(target_os = "uefi", all(target_arch = "x86_64", target_os = "none")))]
 extern crate alloc;
 
-trait BootInfoExt {
-	fn write(self) -> &'static RawBootInfo;
-}
+cfg_if::cfg_if! {
+	if #[cfg(not(test))] {
+		use ::log::info;
+		use hermit_entry::boot_info::{BootInfo, RawBootInfo};
 
-impl BootInfoExt for BootInfo {
-	fn write(self) -> &'static RawBootInfo {
-		info!("boot_info = {self:#x?}");
+		mod arch;
+		#[cfg(target_os = "none")]
+		mod backtrace;
+		mod boot_time;
+		mod bump_allocator;
+		mod entropy;
+		mod error;
+		mod fatal;
+		#[cfg(any(target_os = "uefi", target_arch = "x86_64"))]
+		mod fdt;
+		#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+		mod fdt_patch;
+		mod log;
+		mod log_buffer;
+		mod measured_boot;
+		mod os;
+		mod prompt;
+		mod signature;
+		#[cfg(any(target_os = "uefi", target_arch = "x86_64"))]
+		mod slot;
 
-		take_static::take_static! {
-			static RAW_BOOT_INFO: Option<RawBootInfo> = None;
+		trait BootInfoExt {
+			fn write(self) -> &'static RawBootInfo;
 		}
 
-		let raw_boot_info = RAW_BOOT_INFO.take().unwrap();
+		impl BootInfoExt for BootInfo {
+			fn write(self) -> &'static RawBootInfo {
+				info!("boot_info = {self:#x?}");
 
-		raw_boot_info.insert(RawBootInfo::from(self))
-	}
-}
+				take_static::take_static! {
+					static RAW_BOOT_INFO: Option<RawBootInfo> = None;
+				}
 
-#[doc(hidden)]
-fn _print(args: core::fmt::Arguments<'_>) {
-	use core::fmt::Write;
+				let raw_boot_info = RAW_BOOT_INFO.take().unwrap();
 
-	self::os::CONSOLE.lock().write_fmt(args).unwrap();
+				raw_boot_info.insert(RawBootInfo::from(self))
+			}
+		}
+
+		#[doc(hidden)]
+		fn _print(args: core::fmt::Arguments<'_>) {
+			use core::fmt::Write;
+
+			self::os::CONSOLE.lock().write_fmt(args).unwrap();
+		}
+	}
 }
@@ -0,0 +1,116 @@
+//! Measured boot.
+//!
+//! The loader computes SHA-256 digests of the application image, the command line, and the devicetree, and logs them.
+//! On UEFI, it also measures the environment variables, which may come from the configuration file.
+//! The digests of the application image, the command line, and the environment variables are passed to the kernel in the devicetree.
+//! On UEFI, the application image, the command line, and the environment variables are also extended into the PCRs of the TPM through the `EFI_TCG2_PROTOCOL`.
+
+use core::fmt;
+
+use log::info;
+use one_shot_mutex::OneShotMutex;
+use sha2::{Digest as _, Sha256};
+
+/// The size of a digest in bytes.
+pub const DIGEST_SIZE: usize = 32;
+
+/// A SHA-256 digest.
+pub type Digest = [u8; DIGEST_SIZE];
+
+/// Something that is measured.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Measurement {
+	/// The application image without the signature trailer.
+	Image,
+	/// The command line.
+	Cmdline,
+	/// The environment variables as in `/chosen/hermit,env`.
+	#[cfg_attr(not(target_os = "uefi"), expect(dead_code))]
+	Env,
+	/// The devicetree that is passed to the kernel.
+	Fdt,
+}
+
+impl Measurement {
+	/// Returns the PCR that the measurement is extended into.
+	///
+	/// This follows GRUB, which measures the command line into PCR 8 and the loaded images into PCR 9.
+	/// The devicetree is not extended, since UEFI finishes it after exiting boot services, when the TPM is not available anymore.
+	#[cfg_attr(not(target_os = "uefi"), expect(dead_code))]
+	pub fn pcr(self) -> Option<u32> {
+		match self {
+			Self::Image => Some(9),
+			Self::Cmdline | Self::Env => Some(8),
+			Self::Fdt => None,
+		}
+	}
+
+	/// Returns the description of the measurement in the event log.
+	pub fn description(self) -> &'static str {
+		match self {
+			Self::Image => "hermit-app",
+			Self::Cmdline => "hermit-cmdline",
+			Self::Env => "hermit-env",
+			Self::Fdt => "hermit-fdt",
+		}
+	}
+}
+
+struct Digests {
+	image: Option<Digest>,
+	cmdline: Option<Digest>,
+	env: Option<Digest>,
+}
+
+static DIGESTS: OneShotMutex<Digests> = OneShotMutex::new(Digests {
+	image: None,
+	cmdline: None,
+	env: None,
+});
+
+/// Measures `data` as `measurement`.
+///
+/// On UEFI, this must be called before exiting boot services to extend the measurement into the TPM, except for [`Measurement::Fdt`].
+pub fn measure(measurement: Measurement, data: &[u8]) {
+	let digest: Digest = Sha256::digest(data).into();
+	info!(
+		"Measured {}: sha256:{}",
+		measurement.description(),
+		Hex(&digest)
+	);
+
+	match measurement {
+		Measurement::Image => DIGESTS.lock().image = Some(digest),
+		Measurement::Cmdline => DIGESTS.lock().cmdline = Some(digest),
+		Measurement::Env => DIGESTS.lock().env = Some(digest),
+		Measurement::Fdt => {}
+	}
+
+	#[cfg(target_os = "uefi")]
+	if let Some(pcr) = measurement.pcr() {
+		crate::os::tpm_extend(pcr, measurement.description(), data);
+	}
+}
+
+/// Returns the digest of the application image if it has been measured.
+pub fn image() -> Option<Digest> {
+	DIGESTS.lock().image
+}
+
+/// Returns the digest of the command line if it has been measured.
+pub fn cmdline() -> Option<Digest> {
+	DIGESTS.lock().cmdline
+}
+
+/// Returns the digest of the environment variables if they have been measured.
+pub fn env() -> Option<Digest> {
+	DIGESTS.lock().env
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+	}
+}
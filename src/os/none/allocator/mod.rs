@@ -2,6 +2,8 @@
 
 mod bootstrap;
 
+#[cfg(any(target_arch = "x86_64", target_arch = "riscv64"))]
+use core::mem::MaybeUninit;
 use core::ptr;
 use core::ptr::NonNull;
 
@@ -17,12 +19,16 @@ struct GlobalAllocator {
 	///
 	/// It allows allocations before the heap has been initalized.
 	bootstrap_allocator: Option<BootstrapAllocator<BumpAllocator>>,
+
+	/// The heap, which is used instead of the bootstrap allocator once it has been initialized.
+	heap: Option<BumpAllocator>,
 }
 
 impl GlobalAllocator {
 	const fn empty() -> Self {
 		Self {
 			bootstrap_allocator: None,
+			heap: None,
 		}
 	}
 
@@ -34,9 +40,14 @@ impl GlobalAllocator {
 
 	fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
 		let layout = Self::align_layout(layout);
-		self.bootstrap_allocator
-			.get_or_insert_with(Default::default)
-			.allocate(layout)
+		let allocation = match &self.heap {
+			Some(heap) => heap.allocate(layout),
+			None => self
+				.bootstrap_allocator
+				.get_or_insert_with(Default::default)
+				.allocate(layout),
+		};
+		allocation
 			// FIXME: Use NonNull::as_mut_ptr once `slice_ptr_get` is stabilized
 			// https://github.com/rust-lang/rust/issues/74265
 			.map(|ptr| NonNull::new(ptr.as_ptr() as *mut u8).unwrap())
@@ -44,10 +55,11 @@ impl GlobalAllocator {
 
 	unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
 		let layout = Self::align_layout(layout);
-		let bootstrap_allocator = self.bootstrap_allocator.as_ref().unwrap();
-		assert!(bootstrap_allocator.manages(ptr));
-		unsafe {
-			bootstrap_allocator.deallocate(ptr, layout);
+		match &self.bootstrap_allocator {
+			Some(bootstrap_allocator) if bootstrap_allocator.manages(ptr) => unsafe {
+				bootstrap_allocator.deallocate(ptr, layout);
+			},
+			_ => unsafe { self.heap.as_ref().unwrap().deallocate(ptr, layout) },
 		}
 	}
 }
@@ -84,6 +96,16 @@ unsafe impl GlobalAlloc for LockedAllocator {
 #[global_allocator]
 static ALLOCATOR: LockedAllocator = LockedAllocator::empty();
 
+/// Switches from the bootstrap allocator to a bump allocator over `heap`.
+///
+/// Allocations from the bootstrap allocator stay valid.
+#[cfg(any(target_arch = "x86_64", target_arch = "riscv64"))]
+pub fn init_heap(heap: &'static mut [MaybeUninit<u8>]) {
+	let mut allocator = ALLOCATOR.0.lock();
+	assert!(allocator.heap.is_none());
+	allocator.heap = Some(BumpAllocator::from(heap));
+}
+
 #[cfg(all(test, not(target_os = "none")))]
 mod tests {
 	use core::mem;
@@ -13,10 +13,20 @@ impl Console {
 		Self { console: None }
 	}
 
-	#[cfg(target_arch = "aarch64")]
+	#[cfg(any(
+		target_arch = "aarch64",
+		all(target_arch = "x86_64", not(feature = "fc"))
+	))]
 	pub fn get(&mut self) -> &mut arch::Console {
 		self.console.get_or_insert_with(arch::Console::default)
 	}
+
+	/// Receives a byte from the console if one is available.
+	pub fn read_byte(&mut self) -> Option<u8> {
+		self.console
+			.get_or_insert_with(arch::Console::default)
+			.read_byte()
+	}
 }
 
 impl fmt::Write for Console {
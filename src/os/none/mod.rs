@@ -1,15 +1,21 @@
 mod allocator;
 mod console;
 
-use core::fmt::Write;
+use core::convert::Infallible;
 use core::mem::MaybeUninit;
 use core::slice;
 
 use hermit_entry::elf::KernelObject;
-use log::info;
+use log::{error, info};
 
+#[cfg(any(target_arch = "x86_64", target_arch = "riscv64"))]
+pub use self::allocator::init_heap;
 pub use self::console::CONSOLE;
-use crate::arch;
+use crate::backtrace::Backtrace;
+use crate::boot_time::{self, Phase};
+use crate::error::LoaderError;
+use crate::measured_boot::{self, Measurement};
+use crate::{arch, fatal, kaslr, prompt, signature};
 
 extern "C" {
 	static loader_end: u8;
@@ -20,17 +26,66 @@ extern "C" {
 /// (called from entry.asm or entry.rs)
 #[no_mangle]
 pub(crate) unsafe extern "C" fn loader_main() -> ! {
-	crate::log::init();
+	boot_time::record(Phase::Entry);
+	arch::install_exception_handlers();
+
+	let cmdline = arch::command_line();
+
+	#[cfg(target_arch = "x86_64")]
+	let serial = cmdline.map_or(Ok(()), arch::configure);
+	let fatal = cmdline.map_or(Ok(()), fatal::init);
+	if let Some(cmdline) = cmdline {
+		kaslr::init(cmdline);
+	}
+
+	crate::log::init(cmdline);
+
+	let cmdline = prompt::edit(cmdline);
+	if let Some(cmdline) = cmdline {
+		measured_boot::measure(Measurement::Cmdline, cmdline.as_bytes());
+	}
+
+	if let Err(value) = fatal {
+		log::warn!("Ignoring unknown loader.fatal={value}");
+	}
+
+	#[cfg(target_arch = "x86_64")]
+	if let Err(serial) = serial {
+		log::warn!(
+			"Could not find serial port {:#x}, using {:#x}",
+			serial.port,
+			arch::serial().port
+		);
+	}
 
 	unsafe {
 		info!("Loader: [{:p} - {:p}]", &loader_start, &loader_end);
 	}
 
-	let kernel = arch::find_kernel();
-	let kernel = KernelObject::parse(kernel).unwrap();
+	let Err(err) = unsafe { boot() };
+	error!("{err}");
+
+	fatal::fatal()
+}
+
+/// Loads and boots the kernel.
+///
+/// This only returns on errors.
+unsafe fn boot() -> Result<Infallible, LoaderError> {
+	let kernel = arch::find_kernel()?;
+	let kernel = signature::verify(kernel, arch::detached_signature(), None)?;
+	measured_boot::measure(Measurement::Image, kernel);
+	boot_time::record(Phase::FindKernel);
+	let kernel = KernelObject::parse(kernel)?;
+	boot_time::record(Phase::ParseKernel);
 
 	let mem_size = kernel.mem_size();
-	let kernel_addr = unsafe { arch::get_memory(mem_size as u64) };
+	let random_addr = if kernel.start_addr().is_none() {
+		unsafe { arch::get_random_memory(mem_size as u64) }
+	} else {
+		None
+	};
+	let kernel_addr = random_addr.unwrap_or_else(|| unsafe { arch::get_memory(mem_size as u64) });
 	let kernel_addr = kernel.start_addr().unwrap_or(kernel_addr);
 	let memory = unsafe {
 		slice::from_raw_parts_mut(
@@ -40,6 +95,7 @@ pub(crate) unsafe extern "C" fn loader_main() -> ! {
 	};
 
 	let kernel_info = kernel.load_kernel(memory, memory.as_ptr() as u64);
+	boot_time::record(Phase::LoadKernel);
 
 	unsafe { arch::boot_kernel(kernel_info) }
 }
@@ -47,7 +103,5 @@ pub(crate) unsafe extern "C" fn loader_main() -> ! {
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
 	// We can't use `println!` or related macros, because `_print` unwraps a result and might panic again
-	writeln!(crate::os::CONSOLE.lock(), "[LOADER] {info}").ok();
-
-	loop {}
+	fatal::report(format_args!("{info}\n{}", Backtrace::capture()))
 }
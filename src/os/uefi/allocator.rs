@@ -46,10 +46,13 @@ unsafe impl GlobalAlloc for LockedAllocator {
 #[global_allocator]
 static ALLOCATOR: LockedAllocator = LockedAllocator::uefi();
 
-pub fn exit_boot_services() {
+/// Switches to a bump allocator over a heap of `heap_size` bytes, which is allocated from the UEFI allocator.
+///
+/// This must be called right before exiting boot services.
+pub fn exit_boot_services(heap_size: usize) {
 	assert!(matches!(*ALLOCATOR.0.lock(), GlobalAllocator::Uefi));
 
-	let mem = vec![MaybeUninit::uninit(); 4096].leak();
+	let mem = vec![MaybeUninit::uninit(); heap_size].leak();
 
 	let bump = BumpAllocator::from(mem);
 
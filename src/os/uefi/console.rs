@@ -4,6 +4,7 @@ use core::ptr::NonNull;
 
 use one_shot_mutex::OneShotMutex;
 use uefi::boot::{EventType, Tpl};
+use uefi::proto::console::text::Key;
 use uefi::Event;
 
 use crate::arch;
@@ -26,6 +27,30 @@ impl Console {
 		};
 	}
 
+	/// Writes to `framebuffer` after boot services have been exited.
+	pub fn set_framebuffer(&mut self, framebuffer: arch::Framebuffer) {
+		if let Self::Native { console } = self {
+			console.set_framebuffer(framebuffer);
+		}
+	}
+
+	/// Receives a byte from the console if one is available.
+	///
+	/// Before exiting boot services, this reads from `SimpleTextInput`, which ignores special keys.
+	pub fn read_byte(&mut self) -> Option<u8> {
+		match self {
+			Console::None => {
+				self.init();
+				self.read_byte()
+			}
+			Console::BootServices => match uefi::system::with_stdin(|stdin| stdin.read_key()) {
+				Ok(Some(Key::Printable(c))) => u8::try_from(u16::from(c)).ok(),
+				_ => None,
+			},
+			Console::Native { console } => console.read_byte(),
+		}
+	}
+
 	fn init(&mut self) {
 		assert!(matches!(self, Console::None));
 		unsafe {
@@ -0,0 +1,239 @@
+//! An interactive menu for choosing the application.
+//!
+//! The menu lists `\efi\boot\hermit-app` and all files in the application directory, which is `\efi\hermit` by default.
+//! An application may be described by the first line of `<APP>.txt`.
+//! `\efi\boot\hermit-app` is also listed if only its slots exist (see [`super::slots`]).
+//!
+//! The menu is configured with the following command line arguments:
+//!
+//! - `loader.apps=<DIR>` sets the application directory.
+//! - `loader.default=<NAME>` sets the default application by its file name.
+//! - `loader.timeout=<SECONDS>` sets the time until the default application is booted (default: 5).
+//!   With `0`, the menu is not shown.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use log::{info, warn};
+use uefi::boot::{self, EventType, TimerTrigger, Tpl};
+use uefi::fs::{FileSystem, Path, PathBuf};
+use uefi::proto::console::gop::GraphicsOutput;
+use uefi::proto::console::serial::Serial;
+use uefi::proto::console::text::{Key, ScanCode};
+use uefi::{cstr16, system, CString16, Handle};
+
+use super::slots;
+use crate::cmdline;
+
+/// The default timeout in seconds.
+const DEFAULT_TIMEOUT: u64 = 5;
+
+/// An application on the ESP.
+struct Entry {
+	path: PathBuf,
+	/// The file name of the application.
+	name: String,
+	/// The first line of `<APP>.txt`.
+	description: Option<String>,
+}
+
+/// Returns the path of the application to boot.
+///
+/// This must be called before exiting boot services.
+pub fn select(fs: &mut FileSystem, cmdline: Option<&str>) -> PathBuf {
+	let option = |key| cmdline.and_then(|cmdline| cmdline::values(cmdline, key).last());
+
+	let default_path = Path::new(cstr16!(r"\efi\boot\hermit-app"));
+	let dir = option("loader.apps").unwrap_or(r"\efi\hermit");
+	let mut entries = entries(fs, default_path, dir);
+	if entries.is_empty() {
+		return default_path.to_path_buf();
+	}
+
+	let default = option("loader.default")
+		.and_then(|name| {
+			let default = entries.iter().position(|entry| entry.name == name);
+			if default.is_none() {
+				warn!("Could not find loader.default={name}");
+			}
+			default
+		})
+		.unwrap_or(0);
+
+	let timeout = match option("loader.timeout").map(str::parse) {
+		Some(Ok(timeout)) => timeout,
+		Some(Err(err)) => {
+			warn!("Ignoring invalid loader.timeout: {err}");
+			DEFAULT_TIMEOUT
+		}
+		None => DEFAULT_TIMEOUT,
+	};
+
+	let selected = if entries.len() == 1 || timeout == 0 || is_headless() {
+		default
+	} else {
+		run(&entries, default, timeout)
+	};
+
+	let entry = entries.swap_remove(selected);
+	info!("Booting {}", entry.name);
+	entry.path
+}
+
+/// Returns all applications, starting with the one at `default_path`.
+fn entries(fs: &mut FileSystem, default_path: &Path, dir: &str) -> Vec<Entry> {
+	let mut entries = Vec::new();
+
+	if fs.try_exists(default_path).unwrap_or(false) || slots::has_slots(fs, default_path) {
+		entries.push(Entry::new(
+			fs,
+			default_path.to_path_buf(),
+			"hermit-app".to_string(),
+		));
+	}
+
+	let Ok(dir_path) = CString16::try_from(dir) else {
+		warn!("Ignoring invalid loader.apps={dir}");
+		return entries;
+	};
+	let dir_path = PathBuf::from(dir_path);
+	let Ok(dir) = fs.read_dir(&dir_path) else {
+		return entries;
+	};
+
+	let mut names = dir
+		.filter_map(Result::ok)
+		.filter(|info| !info.is_directory())
+		.map(|info| info.file_name().to_string())
+		.filter(|name| !name.ends_with(".sig") && !name.ends_with(".txt"))
+		.collect::<Vec<_>>();
+	names.sort_unstable();
+
+	for name in names {
+		let mut path = dir_path.clone();
+		path.push(Path::new(&CString16::try_from(name.as_str()).unwrap()));
+		entries.push(Entry::new(fs, path, name));
+	}
+
+	entries
+}
+
+impl Entry {
+	fn new(fs: &mut FileSystem, path: PathBuf, name: String) -> Self {
+		let description = fs
+			.read(with_extension(&path, "txt"))
+			.ok()
+			.and_then(|description| {
+				let description = String::from_utf8_lossy(&description);
+				let line = description.lines().next()?.trim();
+				(!line.is_empty()).then(|| line.to_string())
+			});
+
+		Self {
+			path,
+			name,
+			description,
+		}
+	}
+}
+
+/// Returns `path` with `extension` appended.
+pub fn with_extension(path: &Path, extension: &str) -> PathBuf {
+	let path = format!("{path}.{extension}");
+	PathBuf::from(CString16::try_from(path.as_str()).unwrap())
+}
+
+/// Returns whether there is no console for interacting with the menu.
+///
+/// Firmware usually provides the console protocols even without any console devices,
+/// so we also require a graphics output or a serial port.
+fn is_headless() -> bool {
+	let Some(system_table) = uefi::table::system_table_raw() else {
+		return true;
+	};
+	let system_table = unsafe { system_table.as_ref() };
+	if system_table.stdin.is_null() || system_table.stdout.is_null() {
+		return true;
+	}
+
+	let has_device =
+		|handles: uefi::Result<Vec<Handle>>| handles.is_ok_and(|handles| !handles.is_empty());
+	!has_device(boot::find_handles::<GraphicsOutput>())
+		&& !has_device(boot::find_handles::<Serial>())
+}
+
+/// Shows the menu and returns the index of the chosen entry.
+///
+/// The `default` entry is chosen after `timeout` seconds unless a key is pressed.
+fn run(entries: &[Entry], default: usize, timeout: u64) -> usize {
+	/// One second in units of 100 ns.
+	const SECOND: u64 = 10_000_000;
+
+	let Some(key_event) = system::with_stdin(|stdin| stdin.wait_for_key_event()) else {
+		return default;
+	};
+	let Ok(timer) = (unsafe { boot::create_event(EventType::TIMER, Tpl::APPLICATION, None, None) })
+	else {
+		return default;
+	};
+	if boot::set_timer(&timer, TimerTrigger::Periodic(SECOND)).is_err() {
+		boot::close_event(timer).unwrap();
+		return default;
+	}
+	let mut events = [key_event, unsafe { timer.unsafe_clone() }];
+
+	let mut selected = default;
+	let mut remaining = Some(timeout);
+	loop {
+		draw(entries, selected, remaining);
+
+		match boot::wait_for_event(&mut events) {
+			Ok(0) => {
+				// Any key stops the countdown.
+				remaining = None;
+				match system::with_stdin(|stdin| stdin.read_key()) {
+					Ok(Some(Key::Special(ScanCode::UP))) => {
+						selected = selected.checked_sub(1).unwrap_or(entries.len() - 1);
+					}
+					Ok(Some(Key::Special(ScanCode::DOWN))) => {
+						selected = (selected + 1) % entries.len();
+					}
+					Ok(Some(Key::Special(ScanCode::HOME))) => selected = 0,
+					Ok(Some(Key::Special(ScanCode::END))) => selected = entries.len() - 1,
+					Ok(Some(Key::Printable(c))) if char::from(c) == '\r' => break,
+					_ => {}
+				}
+			}
+			Ok(_) => match &mut remaining {
+				Some(0 | 1) => break,
+				Some(remaining) => *remaining -= 1,
+				None => {}
+			},
+			Err(_) => break,
+		}
+	}
+
+	boot::close_event(timer).unwrap();
+	// The menu is only cosmetic, so failing to clear it does not matter.
+	system::with_stdout(|stdout| stdout.clear()).ok();
+	selected
+}
+
+fn draw(entries: &[Entry], selected: usize, remaining: Option<u64>) {
+	system::with_stdout(|stdout| stdout.clear()).ok();
+
+	println!("Hermit Loader\n");
+	for (i, entry) in entries.iter().enumerate() {
+		let marker = if i == selected { '>' } else { ' ' };
+		match &entry.description {
+			Some(description) => println!(" {marker} {:<24} {description}", entry.name),
+			None => println!(" {marker} {}", entry.name),
+		}
+	}
+
+	println!("\nUse the arrow keys to select an application and Enter to boot it.");
+	if let Some(remaining) = remaining {
+		println!("Booting the selected application in {remaining} s.");
+	}
+}
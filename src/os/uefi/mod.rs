@@ -1,7 +1,14 @@
 mod allocator;
 mod console;
+mod menu;
+mod net;
+mod slots;
+mod volume;
 
+use alloc::format;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use core::convert::Infallible;
 use core::ffi::c_void;
 use core::mem::MaybeUninit;
 use core::slice;
@@ -11,77 +18,329 @@ use hermit_entry::boot_info::{
 	BootInfo, DeviceTreeAddress, HardwareInfo, PlatformInfo, SerialPortBase,
 };
 use hermit_entry::elf::{KernelObject, LoadedKernel};
-use log::info;
+use log::{error, info, warn};
 use sptr::Strict;
-use uefi::boot::{AllocateType, MemoryType, PAGE_SIZE};
+use uefi::boot::{AllocateType, MemoryType, OpenProtocolAttributes, OpenProtocolParams, PAGE_SIZE};
 use uefi::fs::{FileSystem, Path};
+use uefi::guid;
+use uefi::mem::memory_map::{MemoryMap, MemoryMapMut};
 use uefi::prelude::*;
+use uefi::proto::console::gop::{GraphicsOutput, PixelFormat};
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::rng::Rng;
+use uefi::proto::tcg::v2::{HashLogExtendEventFlags, PcrEventInputs, Tcg};
+use uefi::proto::tcg::{EventType, PcrIndex};
+use uefi::runtime::{self, VariableAttributes, VariableVendor};
 use uefi::table::cfg;
 
 pub use self::console::CONSOLE;
+use crate::boot_time::{self, Phase};
+use crate::config::{self, Config};
+use crate::error::LoaderError;
 use crate::fdt::Fdt;
-use crate::{arch, BootInfoExt};
+use crate::measured_boot::{self, Measurement};
+use crate::{arch, cmdline, entropy, fatal, kaslr, prompt, signature, BootInfoExt};
 
 // Entry Point of the Uefi Loader
 #[entry]
 fn main() -> Status {
+	boot_time::record(Phase::Entry);
 	uefi::helpers::init().unwrap();
-	crate::log::init();
 
-	let kernel_image = read_app();
-	let kernel = KernelObject::parse(&kernel_image).unwrap();
+	let cmdline = load_options().map(String::leak).map(|cmdline| &*cmdline);
+	let serial = cmdline.map_or(Ok(()), arch::configure);
+	let fatal = cmdline.map_or(Ok(()), fatal::init);
+	if let Some(cmdline) = cmdline {
+		kaslr::init(cmdline);
+	}
 
-	let kernel_memory = alloc_page_slice(kernel.mem_size()).unwrap();
+	crate::log::init(cmdline);
+
+	let cmdline = prompt::edit(cmdline);
+	if let Some(cmdline) = cmdline {
+		measured_boot::measure(Measurement::Cmdline, cmdline.as_bytes());
+	}
+
+	if let Err(value) = fatal {
+		warn!("Ignoring unknown loader.fatal={value}");
+	}
+
+	if let Err(serial) = serial {
+		warn!(
+			"Could not find serial port {:#x}, using {:#x}",
+			serial.port,
+			arch::serial().port
+		);
+	}
+
+	let (kernel_info, fdt) = match load(cmdline) {
+		Ok(loaded) => loaded,
+		Err(err) => {
+			error!("{err}");
+			return err.status();
+		}
+	};
+
+	let framebuffer = framebuffer();
+
+	// Allocating the heap and the final memory map may add memory map entries before exiting boot services.
+	let memory_nodes = boot::memory_map(MemoryType::LOADER_DATA)
+		.map_or(0, |memory_map| memory_map.len())
+		+ EXTRA_MEMORY_MAP_ENTRIES;
+	let heap_size = fdt.heap_size(memory_nodes);
+	info!("Reserving {heap_size} B of heap for after exiting boot services");
+	allocator::exit_boot_services(heap_size);
+	let mut memory_map = unsafe { boot::exit_boot_services(MemoryType::LOADER_DATA) };
+	arch::install_exception_handlers();
+
+	if let Some(framebuffer) = framebuffer {
+		CONSOLE.lock().set_framebuffer(framebuffer);
+	}
+
+	// We cannot return to the firmware after exiting boot services.
+	let Err(err) = unsafe { boot_kernel(kernel_info, fdt, &mut memory_map, memory_nodes) };
+	error!("{err}");
+
+	fatal::fatal()
+}
+
+/// The number of memory map entries that may be added between sizing the heap and exiting boot services.
+const EXTRA_MEMORY_MAP_ENTRIES: usize = 32;
+
+/// Loads the kernel and describes the platform.
+///
+/// This must be called before exiting boot services.
+fn load(cmdline: Option<&'static str>) -> Result<(LoadedKernel, Fdt<'static>), LoaderError> {
+	let (kernel_image, detached_signature) = match net::app_url(cmdline) {
+		Some(url) => (
+			net::download(&url)?,
+			net::try_download(&format!("{url}.sig"))?,
+		),
+		None => {
+			let (mut fs, app) = volume::open(cmdline)?;
+
+			let path = app.unwrap_or_else(|| menu::select(&mut fs, cmdline));
+			let path = slots::select(&mut fs, &path, cmdline).unwrap_or(path);
+			(
+				read_app(&mut fs, &path)?,
+				read_detached_signature(&mut fs, &path)?,
+			)
+		}
+	};
+
+	let kernel = signature::verify(
+		&kernel_image,
+		detached_signature.as_deref(),
+		enrolled_public_key(),
+	)?;
+	measured_boot::measure(Measurement::Image, kernel);
+	boot_time::record(Phase::FindKernel);
+	let kernel = KernelObject::parse(kernel)?;
+	boot_time::record(Phase::ParseKernel);
+
+	let random_memory = if kernel.start_addr().is_none() {
+		alloc_random_page_slice(kernel.mem_size())
+	} else {
+		None
+	};
+	let kernel_memory = match random_memory {
+		Some(kernel_memory) => kernel_memory,
+		None => alloc_page_slice(kernel.mem_size()).map_err(|error| LoaderError::Uefi {
+			context: "Allocating memory for the kernel",
+			error,
+		})?,
+	};
 	let kernel_memory = &mut kernel_memory[..kernel.mem_size()];
 
 	let kernel_info = kernel.load_kernel(kernel_memory, kernel_memory.as_ptr() as u64);
+	boot_time::record(Phase::LoadKernel);
 
 	let rsdp = rsdp();
 
 	drop(kernel_image);
 
-	let fdt = Fdt::new("uefi")
+	let rsdp = u64::try_from(rsdp.expose_addr()).unwrap();
+	let mut fdt = Fdt::new("uefi")?;
+	if let Some(cmdline) = cmdline::edited() {
+		fdt = fdt.bootargs(cmdline)?;
+	}
+	let config = read_config();
+	let env = config::env(config.as_ref(), cmdline);
+	if !env.is_empty() {
+		// The configuration file is not covered by the command line measurement.
+		let value = env
+			.iter()
+			.flat_map(|var| var.bytes().chain([0]))
+			.collect::<Vec<_>>();
+		measured_boot::measure(Measurement::Env, &value);
+	}
+	fdt = fdt.env(env)?;
+	let mut memory_map =
+		boot::memory_map(MemoryType::LOADER_DATA).map_err(|error| LoaderError::Uefi {
+			context: "Reading the memory map",
+			error,
+		})?;
+	memory_map.sort();
+	let system_table = uefi::table::system_table_raw()
 		.unwrap()
-		.rsdp(u64::try_from(rsdp.expose_addr()).unwrap())
-		.unwrap();
+		.as_ptr()
+		.expose_addr();
+	fdt = fdt.uefi_runtime(u64::try_from(system_table).unwrap(), &memory_map)?;
+	let fdt = arch::describe_platform(fdt, Some(rsdp))?.prebuild()?;
 
-	allocator::exit_boot_services();
-	let mut memory_map = unsafe { boot::exit_boot_services(MemoryType::LOADER_DATA) };
-
-	let fdt = fdt.memory_map(&mut memory_map).unwrap().finish().unwrap();
+	Ok((kernel_info, fdt))
+}
 
-	unsafe { boot_kernel(kernel_info, fdt) }
+/// Returns the load options of the loader image, which are used as the command line.
+fn load_options() -> Option<String> {
+	let loaded_image = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()).ok()?;
+	let options = loaded_image.load_options_as_cstr16().ok()?;
+	Some(options.to_string())
 }
 
-fn read_app() -> Vec<u8> {
-	let image_handle = boot::image_handle();
-	let fs = boot::get_image_file_system(image_handle).expect("should open file system");
+/// Returns the framebuffer of the graphics output protocol.
+///
+/// This must be called before exiting boot services.
+fn framebuffer() -> Option<arch::Framebuffer> {
+	let handle = boot::get_handle_for_protocol::<GraphicsOutput>().ok()?;
+	// Opening the protocol exclusively would disconnect the firmware's console from it.
+	let mut gop = unsafe {
+		boot::open_protocol::<GraphicsOutput>(
+			OpenProtocolParams {
+				handle,
+				agent: boot::image_handle(),
+				controller: None,
+			},
+			OpenProtocolAttributes::GetProtocol,
+		)
+	}
+	.ok()?;
+
+	let mode_info = gop.current_mode_info();
+	let foreground = match mode_info.pixel_format() {
+		PixelFormat::Rgb | PixelFormat::Bgr => 0x00FF_FFFF,
+		PixelFormat::Bitmask => {
+			let bitmask = mode_info.pixel_bitmask()?;
+			bitmask.red | bitmask.green | bitmask.blue
+		}
+		PixelFormat::BltOnly => return None,
+	};
 
-	let path = Path::new(cstr16!(r"\efi\boot\hermit-app"));
+	let (width, height) = mode_info.resolution();
+	let framebuffer = arch::Framebuffer {
+		addr: gop.frame_buffer().as_mut_ptr().expose_addr(),
+		pitch: mode_info.stride() * 4,
+		width,
+		height,
+		bytes_per_pixel: 4,
+		foreground,
+	};
 
-	let data = FileSystem::new(fs)
-		.read(path)
-		.expect("should read file content");
+	framebuffer.is_valid().then_some(framebuffer)
+}
+
+fn read_app(fs: &mut FileSystem, path: &Path) -> Result<Vec<u8>, LoaderError> {
+	let data = fs.read(path).map_err(LoaderError::File)?;
 
 	let len = data.len();
 	info!("Read Hermit application from \"{path}\" (size = {len} B)");
 
-	data
+	Ok(data)
+}
+
+/// Reads the detached signature of the Hermit application if it exists.
+///
+/// The detached signature of `<APP>` is read from `<APP>.sig`.
+fn read_detached_signature(
+	fs: &mut FileSystem,
+	app: &Path,
+) -> Result<Option<Vec<u8>>, LoaderError> {
+	let path = menu::with_extension(app, "sig");
+	if !fs.try_exists(&path).map_err(LoaderError::File)? {
+		return Ok(None);
+	}
+
+	let data = fs.read(&path).map_err(LoaderError::File)?;
+	info!("Read detached signature from \"{path}\"");
+
+	Ok(Some(data))
+}
+
+/// Reads and parses the configuration file `\efi\boot\hermit-loader.conf` if it exists.
+fn read_config() -> Option<Config<'static>> {
+	let path = Path::new(cstr16!(r"\efi\boot\hermit-loader.conf"));
+
+	let fs = boot::get_image_file_system(boot::image_handle()).ok()?;
+	let text = match FileSystem::new(fs).read_to_string(path) {
+		Ok(text) => text,
+		Err(uefi::fs::Error::Io(err)) if err.uefi_error.status() == Status::NOT_FOUND => {
+			return None;
+		}
+		Err(err) => {
+			warn!("Could not read the configuration file: {err}");
+			return None;
+		}
+	};
+
+	info!("Using the configuration file {path}");
+	Some(Config::parse(String::leak(text)))
 }
 
-pub unsafe fn boot_kernel(kernel_info: LoadedKernel, fdt: Vec<u8>) -> ! {
+/// The vendor GUID of the loader's UEFI variables.
+const VENDOR: VariableVendor = VariableVendor(guid!("8609a14c-5909-4d86-b2f7-dc84e0853d78"));
+
+/// Returns the public key for verifying the application from the UEFI variable `HermitPublicKey`.
+///
+/// The variable is ignored if it can be written by the operating system or does not persist across reboots.
+fn enrolled_public_key() -> Option<[u8; signature::PUBLIC_KEY_SIZE]> {
+	let mut key = [0; signature::PUBLIC_KEY_SIZE];
+	let (value, attributes) =
+		match runtime::get_variable(cstr16!("HermitPublicKey"), &VENDOR, &mut key) {
+			Ok(variable) => variable,
+			Err(err) if err.status() == Status::BUFFER_TOO_SMALL => {
+				let len = err.data().unwrap_or_default();
+				warn!("Ignoring HermitPublicKey with a length of {len} B");
+				return None;
+			}
+			Err(_) => return None,
+		};
+	let len = value.len();
+	if len != signature::PUBLIC_KEY_SIZE {
+		warn!("Ignoring HermitPublicKey with a length of {len} B");
+		return None;
+	}
+	if attributes.contains(VariableAttributes::RUNTIME_ACCESS) {
+		warn!("Ignoring HermitPublicKey, which is accessible at runtime");
+		return None;
+	}
+	if !attributes.contains(VariableAttributes::NON_VOLATILE) {
+		warn!("Ignoring HermitPublicKey, which is volatile");
+		return None;
+	}
+
+	info!("Found enrolled public key HermitPublicKey");
+	Some(key)
+}
+
+pub unsafe fn boot_kernel(
+	kernel_info: LoadedKernel,
+	fdt: Fdt<'_>,
+	memory_map: &mut impl MemoryMapMut,
+	memory_nodes: usize,
+) -> Result<Infallible, LoaderError> {
 	let LoadedKernel {
 		load_info,
 		entry_point,
 	} = kernel_info;
 
-	let device_tree =
-		DeviceTreeAddress::new(u64::try_from(fdt.leak().as_ptr().expose_addr()).unwrap());
+	let fdt = fdt.memory_map(memory_map, memory_nodes)?.finish()?.leak();
+
+	let device_tree = DeviceTreeAddress::new(u64::try_from(fdt.as_ptr().expose_addr()).unwrap());
 
 	let boot_info = BootInfo {
 		hardware_info: HardwareInfo {
 			phys_addr_range: 0..0,
-			serial_port_base: SerialPortBase::new(arch::SERIAL_IO_PORT),
+			serial_port_base: SerialPortBase::new(arch::serial().port),
 			device_tree,
 		},
 		load_info,
@@ -94,6 +353,8 @@ pub unsafe fn boot_kernel(kernel_info: LoadedKernel, fdt: Vec<u8>) -> ! {
 	let entry = sptr::from_exposed_addr(entry_point.try_into().unwrap());
 	let stack = sptr::from_exposed_addr_mut(stack);
 	let raw_boot_info = boot_info.write();
+	boot_time::finish(Some(fdt));
+	measured_boot::measure(Measurement::Fdt, fdt);
 
 	unsafe { arch::enter_kernel(stack, entry, raw_boot_info) }
 }
@@ -108,6 +369,74 @@ fn alloc_page_slice(size: usize) -> uefi::Result<&'static mut [MaybeUninit<u8>]>
 	Ok(unsafe { slice::from_raw_parts_mut(ptr.cast().as_ptr(), size) })
 }
 
+/// Allocates pages at a random address for a relocatable kernel.
+///
+/// This must be called before exiting boot services.
+fn alloc_random_page_slice(size: usize) -> Option<&'static mut [MaybeUninit<u8>]> {
+	let size = size.align_up(PAGE_SIZE);
+	let usable = boot::memory_map(MemoryType::LOADER_DATA)
+		.ok()?
+		.entries()
+		.filter(|entry| entry.ty == MemoryType::CONVENTIONAL)
+		.map(|entry| entry.phys_start..entry.phys_start + entry.page_count * PAGE_SIZE as u64)
+		.collect::<Vec<_>>();
+
+	let addr = kaslr::choose(
+		|| usable.iter().cloned(),
+		core::iter::empty,
+		size as u64,
+		entropy::kaslr_seed,
+	)?;
+	let ptr = boot::allocate_pages(
+		AllocateType::Address(addr),
+		MemoryType::LOADER_DATA,
+		size / PAGE_SIZE,
+	)
+	.inspect_err(|err| warn!("Could not allocate memory for the kernel at {addr:#x}: {err}"))
+	.ok()?;
+	Some(unsafe { slice::from_raw_parts_mut(ptr.cast().as_ptr(), size) })
+}
+
+/// Fills `buf` with random bytes from the `EFI_RNG_PROTOCOL`.
+///
+/// This must be called before exiting boot services.
+pub fn fill_random(buf: &mut [u8]) -> bool {
+	let Ok(handle) = boot::get_handle_for_protocol::<Rng>() else {
+		return false;
+	};
+	let Ok(mut rng) = boot::open_protocol_exclusive::<Rng>(handle) else {
+		return false;
+	};
+	rng.get_rng(None, buf).is_ok()
+}
+
+/// Extends `data` into `pcr` of the TPM and logs it as `description` through the `EFI_TCG2_PROTOCOL`.
+///
+/// This must be called before exiting boot services.
+pub fn tpm_extend(pcr: u32, description: &str, data: &[u8]) {
+	let Ok(handle) = boot::get_handle_for_protocol::<Tcg>() else {
+		return;
+	};
+	let Ok(mut tcg) = boot::open_protocol_exclusive::<Tcg>(handle) else {
+		return;
+	};
+	if !tcg
+		.get_capability()
+		.is_ok_and(|capability| capability.tpm_present())
+	{
+		return;
+	}
+
+	let result = PcrEventInputs::new_in_box(PcrIndex(pcr), EventType::IPL, description.as_bytes())
+		.and_then(|event| {
+			tcg.hash_log_extend_event(HashLogExtendEventFlags::empty(), data, &event)
+		});
+	match result {
+		Ok(()) => info!("Extended {description} into PCR {pcr}"),
+		Err(err) => warn!("Could not extend {description} into PCR {pcr}: {err}"),
+	}
+}
+
 /// Returns the RSDP.
 ///
 /// This must be called before exiting boot services.
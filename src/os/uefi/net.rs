@@ -0,0 +1,404 @@
+//! Downloading the application over the network.
+//!
+//! The application is downloaded if `loader.url=<URL>` is given or if the loader itself was booted over the network.
+//! In the latter case, the application is `hermit-app` next to the loader on the server.
+//! `tftp://<IPV4>/<PATH>` URLs are downloaded with the `EFI_PXE_BASE_CODE_PROTOCOL`,
+//! and `http://` and `https://` URLs with the `EFI_HTTP_PROTOCOL`.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::ffi::CStr;
+use core::net::Ipv4Addr;
+use core::ptr;
+
+use log::{info, warn};
+use uefi::boot::{self, EventType, OpenProtocolParams, ScopedProtocol, SearchType, Tpl};
+use uefi::prelude::*;
+use uefi::proto::device_path::{DeviceSubType, DeviceType, LoadedImageDevicePath};
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::network::pxe::{BaseCode, DhcpV4Packet};
+use uefi::proto::network::IpAddress;
+use uefi::proto::unsafe_protocol;
+use uefi::{CStr8, CString16, Identify};
+use uefi_raw::protocol::driver::ServiceBindingProtocol;
+use uefi_raw::protocol::network::http::{
+	HttpAccessPoint, HttpConfigData, HttpHeader, HttpMessage, HttpMethod, HttpProtocol,
+	HttpRequestData, HttpRequestOrResponse, HttpResponseData, HttpStatusCode, HttpToken,
+	HttpV4AccessPoint, HttpVersion,
+};
+use uefi_raw::Ipv4Address;
+
+use crate::cmdline;
+use crate::error::LoaderError;
+
+/// Returns the URL of the application if it is downloaded over the network.
+///
+/// This must be called before exiting boot services.
+pub fn app_url(cmdline: Option<&str>) -> Option<String> {
+	if let Some(url) = cmdline.and_then(|cmdline| cmdline::values(cmdline, "loader.url").last()) {
+		return Some(url.to_string());
+	}
+
+	let url = boot_url()?;
+	info!("Loader was booted from \"{url}\"");
+	let dir = &url[..url.rfind('/')? + 1];
+	Some(format!("{dir}hermit-app"))
+}
+
+/// Returns the URL that the loader was booted from if it was booted over the network.
+fn boot_url() -> Option<String> {
+	// HTTP boot records the URL in the device path of the loader.
+	if let Ok(device_path) =
+		boot::open_protocol_exclusive::<LoadedImageDevicePath>(boot::image_handle())
+	{
+		let uri = device_path
+			.node_iter()
+			.find(|node| node.full_type() == (DeviceType::MESSAGING, DeviceSubType::MESSAGING_URI));
+		if let Some(uri) = uri {
+			let uri = core::str::from_utf8(uri.data()).ok()?;
+			return Some(uri.trim_end_matches('\0').to_string());
+		}
+	}
+
+	// PXE boot leaves the DHCP packets in the PXE base code protocol of the boot device.
+	let device = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle())
+		.ok()?
+		.device()?;
+	let pxe = boot::open_protocol_exclusive::<BaseCode>(device).ok()?;
+	let mode = pxe.mode();
+	if !mode.dhcp_ack_received || mode.using_ipv6 {
+		return None;
+	}
+
+	let packets = [
+		mode.proxy_offer_received.then_some(&mode.proxy_offer),
+		Some(&mode.dhcp_ack),
+	];
+	let url = packets.into_iter().flatten().find_map(|packet| {
+		let packet: &DhcpV4Packet = packet.as_ref();
+		let file = CStr::from_bytes_until_nul(&packet.bootp_boot_file).ok()?;
+		let file = file.to_str().ok().filter(|file| !file.is_empty())?;
+		let server = Ipv4Addr::from(packet.bootp_si_addr);
+		Some(format!("tftp://{server}/{}", file.trim_start_matches('/')))
+	});
+	url
+}
+
+/// Downloads `url`.
+///
+/// This must be called before exiting boot services.
+pub fn download(url: &str) -> Result<Vec<u8>, LoaderError> {
+	try_download(url)?.ok_or_else(|| LoaderError::Download {
+		url: url.to_string(),
+		error: Status::NOT_FOUND.into(),
+	})
+}
+
+/// Downloads `url` if it exists.
+///
+/// This must be called before exiting boot services.
+pub fn try_download(url: &str) -> Result<Option<Vec<u8>>, LoaderError> {
+	let data = match url.split_once("://") {
+		Some(("tftp", location)) => tftp(location),
+		Some(("http" | "https", _)) => http(url),
+		_ => Err(Status::UNSUPPORTED.into()),
+	};
+
+	match data {
+		Ok(data) => {
+			let len = data.len();
+			info!("Downloaded \"{url}\" (size = {len} B)");
+			Ok(Some(data))
+		}
+		Err(error) if error.status() == Status::NOT_FOUND => Ok(None),
+		Err(error) => Err(LoaderError::Download {
+			url: url.to_string(),
+			error,
+		}),
+	}
+}
+
+/// Downloads `<SERVER>/<PATH>` with TFTP.
+fn tftp(location: &str) -> uefi::Result<Vec<u8>> {
+	let (server, path) = location.split_once('/').ok_or(Status::INVALID_PARAMETER)?;
+	let server = server
+		.parse::<Ipv4Addr>()
+		.map_err(|_| Status::INVALID_PARAMETER)?;
+	let server = IpAddress::new_v4(server.octets());
+	let path = format!("{path}\0");
+	let path =
+		CStr8::from_bytes_with_nul(path.as_bytes()).map_err(|_| Status::INVALID_PARAMETER)?;
+
+	let mut pxe = open_pxe()?;
+	let not_found = |pxe: &ScopedProtocol<BaseCode>, error: uefi::Error| {
+		let mode = pxe.mode();
+		// TFTP error code 1 is "File not found".
+		if error.status() == Status::TFTP_ERROR
+			&& mode.tftp_error_received
+			&& mode.tftp_error.error_code == 1
+		{
+			Status::NOT_FOUND.into()
+		} else {
+			error
+		}
+	};
+
+	let size = pxe
+		.tftp_get_file_size(&server, path)
+		.map_err(|error| not_found(&pxe, error))?;
+	let mut data = vec![0; usize::try_from(size).unwrap()];
+	if !data.is_empty() {
+		pxe.tftp_read_file(&server, path, Some(&mut data))
+			.map_err(|error| not_found(&pxe, error))?;
+	}
+
+	Ok(data)
+}
+
+/// Opens the PXE base code protocol of the boot device or else of the first network device.
+fn open_pxe() -> uefi::Result<ScopedProtocol<BaseCode>> {
+	let device = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle())?.device();
+	let has_pxe = |device| {
+		let params = OpenProtocolParams {
+			handle: device,
+			agent: boot::image_handle(),
+			controller: None,
+		};
+		boot::test_protocol::<BaseCode>(params).unwrap_or(false)
+	};
+	let handle = match device.filter(|device| has_pxe(*device)) {
+		Some(device) => device,
+		None => boot::get_handle_for_protocol::<BaseCode>()?,
+	};
+
+	let mut pxe = boot::open_protocol_exclusive::<BaseCode>(handle)?;
+	if !pxe.mode().started {
+		pxe.start(false)?;
+	}
+	if !pxe.mode().dhcp_ack_received {
+		info!("Configuring the network with DHCP");
+		pxe.dhcp(false)?;
+	}
+
+	Ok(pxe)
+}
+
+#[repr(transparent)]
+#[unsafe_protocol(HttpProtocol::SERVICE_BINDING_GUID)]
+struct HttpServiceBinding(ServiceBindingProtocol);
+
+#[repr(transparent)]
+#[unsafe_protocol(HttpProtocol::GUID)]
+struct Http(HttpProtocol);
+
+/// Downloads `url` with HTTP.
+fn http(url: &str) -> uefi::Result<Vec<u8>> {
+	let handles = boot::locate_handle_buffer(SearchType::ByProtocol(&HttpServiceBinding::GUID))
+		.map_err(|_| Status::UNSUPPORTED)?;
+	let handle = *handles.first().ok_or(Status::UNSUPPORTED)?;
+	let mut service_binding = boot::open_protocol_exclusive::<HttpServiceBinding>(handle)?;
+
+	let mut child = ptr::null_mut();
+	unsafe { (service_binding.0.create_child)(&mut service_binding.0, &mut child) }.to_result()?;
+	let child_handle = unsafe { Handle::from_ptr(child) }.ok_or(Status::DEVICE_ERROR)?;
+
+	let data =
+		boot::open_protocol_exclusive::<Http>(child_handle).and_then(|mut http| http.download(url));
+
+	let status = unsafe { (service_binding.0.destroy_child)(&mut service_binding.0, child) };
+	if let Err(err) = status.to_result() {
+		warn!("Could not destroy the HTTP child: {err}");
+	}
+
+	data
+}
+
+impl Http {
+	/// The timeout of requests and responses in milliseconds.
+	const TIMEOUT: u32 = 10_000;
+
+	fn download(&mut self, url: &str) -> uefi::Result<Vec<u8>> {
+		// Use the address from the firmware's network configuration.
+		let access_point = HttpV4AccessPoint {
+			use_default_addr: true,
+			local_address: Ipv4Address([0; 4]),
+			local_subnet: Ipv4Address([0; 4]),
+			local_port: 0,
+		};
+		let config = HttpConfigData {
+			http_version: HttpVersion::HTTP_VERSION_11,
+			time_out_millisec: Self::TIMEOUT,
+			local_addr_is_ipv6: false,
+			access_point: HttpAccessPoint {
+				ipv4_node: &access_point,
+			},
+		};
+		unsafe { (self.0.configure)(&mut self.0, &config) }.to_result()?;
+
+		let url16 = CString16::try_from(url).map_err(|_| Status::INVALID_PARAMETER)?;
+		let host = url
+			.split_once("://")
+			.and_then(|(_, rest)| rest.split('/').next())
+			.ok_or(Status::INVALID_PARAMETER)?;
+		let host = format!("{host}\0");
+
+		let request_data = HttpRequestData {
+			method: HttpMethod::GET,
+			url: url16.as_ptr().cast(),
+		};
+		let mut headers = [
+			HttpHeader {
+				field_name: c"Host".as_ptr().cast(),
+				field_value: host.as_ptr(),
+			},
+			HttpHeader {
+				field_name: c"User-Agent".as_ptr().cast(),
+				field_value: c"hermit-loader".as_ptr().cast(),
+			},
+		];
+		let mut request = HttpMessage {
+			data: HttpRequestOrResponse {
+				request: &request_data,
+			},
+			header_count: headers.len(),
+			header: headers.as_mut_ptr(),
+			body_length: 0,
+			body: ptr::null_mut(),
+		};
+		self.submit(self.0.request, &mut request)?;
+
+		// The first response only contains the status and the headers.
+		// The headers are allocated by the firmware and must be freed by us.
+		let response_data = HttpResponseData {
+			status_code: HttpStatusCode::STATUS_UNSUPPORTED,
+		};
+		let mut response = HttpMessage {
+			data: HttpRequestOrResponse {
+				response: &response_data,
+			},
+			header_count: 0,
+			header: ptr::null_mut(),
+			body_length: 0,
+			body: ptr::null_mut(),
+		};
+		self.submit(self.0.response, &mut response)?;
+		let content_length = unsafe { content_length(&response) };
+		unsafe { free_headers(&response) };
+
+		match response_data.status_code {
+			HttpStatusCode::STATUS_200_OK => {}
+			HttpStatusCode::STATUS_404_NOT_FOUND => return Err(Status::NOT_FOUND.into()),
+			status_code => {
+				warn!("Unexpected HTTP status {status_code:?}");
+				return Err(Status::HTTP_ERROR.into());
+			}
+		}
+
+		let content_length = content_length.ok_or_else(|| {
+			warn!("HTTP response without Content-Length");
+			Status::PROTOCOL_ERROR
+		})?;
+
+		let mut data = Vec::new();
+		if data.try_reserve_exact(content_length).is_err() {
+			warn!("Could not allocate {content_length} B for the HTTP response");
+			return Err(Status::OUT_OF_RESOURCES.into());
+		}
+		data.resize(content_length, 0);
+		let mut received = 0;
+		while received < content_length {
+			let mut body = HttpMessage {
+				data: HttpRequestOrResponse {
+					response: ptr::null(),
+				},
+				header_count: 0,
+				header: ptr::null_mut(),
+				body_length: content_length - received,
+				body: data[received..].as_mut_ptr().cast(),
+			};
+			self.submit(self.0.response, &mut body)?;
+			if body.body_length == 0 {
+				return Err(Status::END_OF_FILE.into());
+			}
+			received += body.body_length;
+		}
+
+		Ok(data)
+	}
+
+	/// Submits `message` with `f` and waits for the completion.
+	fn submit(
+		&mut self,
+		f: unsafe extern "efiapi" fn(*mut HttpProtocol, *mut HttpToken) -> Status,
+		message: &mut HttpMessage,
+	) -> uefi::Result {
+		let event = unsafe { boot::create_event(EventType::empty(), Tpl::CALLBACK, None, None)? };
+		let mut token = HttpToken {
+			event: event.as_ptr(),
+			status: Status::NOT_READY,
+			message,
+		};
+
+		let result = unsafe { f(&mut self.0, &mut token) }
+			.to_result()
+			.and_then(|()| loop {
+				if boot::check_event(unsafe { event.unsafe_clone() })? {
+					break Ok(());
+				}
+				// Errors of polling are reported in the token.
+				let _ = unsafe { (self.0.poll)(&mut self.0) };
+			});
+
+		boot::close_event(event)?;
+		result?;
+		token.status.to_result()
+	}
+}
+
+/// Returns the `Content-Length` from the headers of `response`.
+///
+/// # Safety
+///
+/// The headers must be valid as returned by the firmware.
+unsafe fn content_length(response: &HttpMessage) -> Option<usize> {
+	unsafe { headers(response) }.iter().find_map(|header| {
+		let name = unsafe { CStr::from_ptr(header.field_name.cast()) };
+		let value = unsafe { CStr::from_ptr(header.field_value.cast()) };
+		name.to_bytes()
+			.eq_ignore_ascii_case(b"Content-Length")
+			.then(|| value.to_str().ok()?.trim().parse::<usize>().ok())
+			.flatten()
+	})
+}
+
+/// Frees the headers of `response`, which the firmware allocates from pool memory.
+///
+/// # Safety
+///
+/// The headers must be valid as returned by the firmware and must not be used afterwards.
+unsafe fn free_headers(response: &HttpMessage) {
+	for header in unsafe { headers(response) } {
+		for field in [header.field_name, header.field_value] {
+			if let Some(field) = ptr::NonNull::new(field.cast_mut()) {
+				unsafe { boot::free_pool(field.cast()) }.ok();
+			}
+		}
+	}
+	if let Some(headers) = ptr::NonNull::new(response.header) {
+		unsafe { boot::free_pool(headers.cast()) }.ok();
+	}
+}
+
+/// Returns the headers of `response`.
+///
+/// # Safety
+///
+/// The headers must be valid as returned by the firmware.
+unsafe fn headers(response: &HttpMessage) -> &[HttpHeader] {
+	if response.header.is_null() {
+		&[]
+	} else {
+		unsafe { core::slice::from_raw_parts(response.header, response.header_count) }
+	}
+}
@@ -0,0 +1,146 @@
+//! A/B slots of the application with boot attempt counting.
+//!
+//! The slots of `<APP>` are `<APP>.a` and `<APP>.b`.
+//! The boot state is stored in the UEFI variable `HermitBootState`, which consists of two bytes:
+//! the index of the active slot (`0` for `a`, `1` for `b`) and the number of boot attempts that have not been marked successful.
+//! The kernel marks a boot successful by resetting the number of attempts to `0` through the UEFI runtime services,
+//! which are passed to it in `/chosen/hermit,uefi-system-table` and `/reserved-memory`.
+//! After `loader.slot-attempts` (default: 3) unsuccessful attempts, the loader falls back to the other slot.
+//! If neither slot is ever marked successful, the loader keeps alternating between them,
+//! so that a slot that only failed temporarily gets another chance.
+//! `loader.slot=<a|b>` boots a slot regardless of the boot state.
+
+use log::{info, warn};
+use uefi::fs::{FileSystem, Path, PathBuf};
+use uefi::runtime::{self, VariableAttributes};
+use uefi::{cstr16, CStr16};
+
+use super::{menu, VENDOR};
+use crate::cmdline;
+use crate::slot::{self, Booted, Slot};
+
+/// The name of the UEFI variable with the boot state.
+const STATE_VARIABLE: &CStr16 = cstr16!("HermitBootState");
+
+/// The name of the UEFI variable with the boot state as in efivarfs.
+const STATE_VARIABLE_EFIVARFS: &str = "HermitBootState-8609a14c-5909-4d86-b2f7-dc84e0853d78";
+
+/// The default number of unsuccessful boot attempts before falling back to the other slot.
+const DEFAULT_ATTEMPTS: u8 = 3;
+
+/// The boot state in `HermitBootState`.
+struct State {
+	slot: Slot,
+	attempts: u8,
+}
+
+impl State {
+	fn read() -> Option<Self> {
+		let mut buf = [0; 2];
+		let (value, _attributes) = runtime::get_variable(STATE_VARIABLE, &VENDOR, &mut buf).ok()?;
+		let [slot, attempts] = *value else {
+			warn!(
+				"Ignoring HermitBootState with a length of {} B",
+				value.len()
+			);
+			return None;
+		};
+		let slot = Slot::from_index(slot)?;
+		Some(Self { slot, attempts })
+	}
+
+	fn write(&self) {
+		// The kernel has to be able to mark the boot successful.
+		let attributes = VariableAttributes::NON_VOLATILE
+			| VariableAttributes::BOOTSERVICE_ACCESS
+			| VariableAttributes::RUNTIME_ACCESS;
+		let value = [self.slot.index(), self.attempts];
+		if let Err(err) = runtime::set_variable(STATE_VARIABLE, &VENDOR, attributes, &value) {
+			warn!("Could not write HermitBootState: {err}");
+		}
+	}
+}
+
+/// Returns the path of `<APP>.<SLOT>`.
+fn slot_path(app: &Path, slot: Slot) -> PathBuf {
+	menu::with_extension(app, slot.name())
+}
+
+/// Returns whether `app` has any slot.
+pub fn has_slots(fs: &mut FileSystem, app: &Path) -> bool {
+	[Slot::A, Slot::B]
+		.into_iter()
+		.any(|slot| fs.try_exists(slot_path(app, slot)).unwrap_or(false))
+}
+
+/// Returns the path of the slot to boot if `app` has slots and updates the boot state.
+///
+/// This must be called before exiting boot services.
+pub fn select(fs: &mut FileSystem, app: &Path, cmdline: Option<&str>) -> Option<PathBuf> {
+	let mut exists = |slot| fs.try_exists(slot_path(app, slot)).unwrap_or(false);
+	let available = [exists(Slot::A), exists(Slot::B)];
+	if available == [false, false] {
+		return None;
+	}
+
+	let option = |key| cmdline.and_then(|cmdline| cmdline::values(cmdline, key).last());
+	let max_attempts = match option("loader.slot-attempts").map(str::parse) {
+		Some(Ok(max_attempts)) => max_attempts,
+		Some(Err(err)) => {
+			warn!("Ignoring invalid loader.slot-attempts: {err}");
+			DEFAULT_ATTEMPTS
+		}
+		None => DEFAULT_ATTEMPTS,
+	};
+	let exists = |slot: Slot| available[usize::from(slot.index())];
+
+	let forced = cmdline.and_then(slot::from_cmdline).filter(|slot| {
+		let exists = exists(*slot);
+		if !exists {
+			warn!("Could not find slot {slot}");
+		}
+		exists
+	});
+
+	let slot = if let Some(slot) = forced {
+		info!("Booting slot {slot} from loader.slot");
+		slot
+	} else {
+		let mut state = State::read().unwrap_or(State {
+			slot: Slot::A,
+			attempts: 0,
+		});
+
+		if !exists(state.slot) {
+			state = State {
+				slot: state.slot.other(),
+				attempts: 0,
+			};
+		} else if state.attempts >= max_attempts && exists(state.slot.other()) {
+			warn!(
+				"Slot {} was not marked successful after {} attempts, falling back to slot {}",
+				state.slot,
+				state.attempts,
+				state.slot.other()
+			);
+			state = State {
+				slot: state.slot.other(),
+				attempts: 0,
+			};
+		}
+
+		state.attempts = state.attempts.saturating_add(1);
+		state.write();
+		info!(
+			"Booting slot {} (attempt {}/{max_attempts})",
+			state.slot, state.attempts
+		);
+		state.slot
+	};
+
+	slot::set_booted(Booted {
+		slot,
+		state_variable: Some(STATE_VARIABLE_EFIVARFS),
+	});
+	Some(slot_path(app, slot))
+}
@@ -0,0 +1,126 @@
+//! Finding the file system that contains the application.
+//!
+//! By default, the application is read from the file system of the loader.
+//! With `loader.fs=<SELECTOR>`, the loader reads the application from the first file system that matches the selector:
+//!
+//! - `label:<LABEL>` matches the name of the GPT partition.
+//! - `type:<GUID>` matches the type GUID of the GPT partition.
+//! - `path:<PATH>` matches file systems that contain the file or directory `<PATH>`.
+//!   If `<PATH>` is a file, it is the application.
+
+use log::info;
+use uefi::fs::{FileSystem, PathBuf};
+use uefi::prelude::*;
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::proto::media::partition::PartitionInfo;
+use uefi::{boot, CStr16, CString16, Guid};
+
+use crate::cmdline;
+use crate::error::LoaderError;
+
+enum Selector {
+	Label(CString16),
+	Type(Guid),
+	Path(PathBuf),
+}
+
+impl Selector {
+	fn parse(selector: &str) -> Option<Self> {
+		let (kind, value) = selector.split_once(':')?;
+		match kind {
+			"label" => CString16::try_from(value).ok().map(Self::Label),
+			"type" => value.parse().ok().map(Self::Type),
+			"path" => CString16::try_from(value)
+				.ok()
+				.map(|path| Self::Path(PathBuf::from(path))),
+			_ => None,
+		}
+	}
+
+	/// Returns whether the GPT partition of `handle` matches this selector.
+	fn matches_partition(&self, handle: Handle) -> bool {
+		let Ok(partition_info) = boot::open_protocol_exclusive::<PartitionInfo>(handle) else {
+			return false;
+		};
+		let Some(entry) = partition_info.gpt_partition_entry() else {
+			return false;
+		};
+
+		match self {
+			Self::Label(label) => {
+				let name = entry.partition_name;
+				CStr16::from_char16_until_nul(&name).is_ok_and(|name| name == &**label)
+			}
+			Self::Type(guid) => {
+				let partition_type = entry.partition_type_guid;
+				partition_type.0 == *guid
+			}
+			Self::Path(_) => false,
+		}
+	}
+
+	/// Returns `<PATH>` if this is a `path:<PATH>` selector that names a file on `fs`.
+	fn app(&self, fs: &mut FileSystem) -> Option<PathBuf> {
+		let Self::Path(path) = self else {
+			return None;
+		};
+		let info = fs.metadata(path).ok()?;
+		(!info.is_directory()).then(|| path.clone())
+	}
+
+	/// Opens the file system of `handle` if it matches this selector.
+	fn open(&self, handle: Handle) -> Option<FileSystem> {
+		if let Self::Label(_) | Self::Type(_) = self {
+			if !self.matches_partition(handle) {
+				return None;
+			}
+		}
+
+		let fs = boot::open_protocol_exclusive::<SimpleFileSystem>(handle).ok()?;
+		let mut fs = FileSystem::new(fs);
+		if let Self::Path(path) = self {
+			if !fs.try_exists(path).unwrap_or(false) {
+				return None;
+			}
+		}
+
+		Some(fs)
+	}
+}
+
+/// Opens the file system that contains the application.
+///
+/// Also returns the path of the application if `loader.fs=path:<PATH>` names a file.
+/// This must be called before exiting boot services.
+pub fn open(cmdline: Option<&str>) -> Result<(FileSystem, Option<PathBuf>), LoaderError> {
+	let Some(value) = cmdline.and_then(|cmdline| cmdline::values(cmdline, "loader.fs").last())
+	else {
+		let image_handle = boot::image_handle();
+		let fs = boot::get_image_file_system(image_handle).map_err(|error| LoaderError::Uefi {
+			context: "Opening the file system of the loader",
+			error,
+		})?;
+		return Ok((FileSystem::new(fs), None));
+	};
+
+	let selector = Selector::parse(value).ok_or(LoaderError::Option {
+		name: "loader.fs",
+		expected: "label:<LABEL>, type:<GUID>, or path:<PATH>",
+	})?;
+
+	let handles = boot::find_handles::<SimpleFileSystem>().map_err(|error| LoaderError::Uefi {
+		context: "Finding file systems",
+		error,
+	})?;
+	let mut fs = handles
+		.into_iter()
+		.find_map(|handle| selector.open(handle))
+		.ok_or(LoaderError::Uefi {
+			context: "Finding a file system for loader.fs",
+			error: Status::NOT_FOUND.into(),
+		})?;
+
+	info!("Reading the application from the file system with loader.fs={value}");
+	let app = selector.app(&mut fs);
+	Ok((fs, app))
+}
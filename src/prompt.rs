@@ -0,0 +1,145 @@
+//! An interactive prompt for editing the command line on the console.
+//!
+//! With `loader.prompt=<MS>`, the loader waits up to `<MS>` milliseconds for a key before booting.
+//! `LOADER_PROMPT` at build time sets the default, which is `0` (no prompt).
+//! If a key is pressed, the command line can be edited in a minimal line editor:
+//! printable characters are appended, Backspace deletes the last character, Ctrl-U clears the line,
+//! Enter accepts the line, and Ctrl-C keeps the original command line.
+//!
+//! The edited command line is passed to the kernel in `/chosen/bootargs` and used for the loader options that are read afterwards.
+//! The console, logging, `loader.fatal`, and `nokaslr` have already been configured from the original command line.
+
+use alloc::string::String;
+use core::hint;
+
+use log::{info, warn};
+
+use crate::{arch, cmdline, os};
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const ESCAPE: u8 = 0x1b;
+const DELETE: u8 = 0x7f;
+
+/// The default timeout of the prompt in milliseconds.
+const DEFAULT_TIMEOUT: u64 = match option_env!("LOADER_PROMPT") {
+	Some(timeout) => match u64::from_str_radix(timeout, 10) {
+		Ok(timeout) => timeout,
+		Err(_) => panic!("LOADER_PROMPT must be a number of milliseconds"),
+	},
+	None => 0,
+};
+
+/// Offers editing `cmdline` on the console and returns the command line to use.
+pub fn edit(cmdline: Option<&'static str>) -> Option<&'static str> {
+	let timeout = timeout(cmdline);
+	if timeout == 0 {
+		return cmdline;
+	}
+
+	let Some(frequency) = timestamp_frequency() else {
+		warn!("Skipping the boot argument prompt, because the timestamp frequency is unknown");
+		return cmdline;
+	};
+
+	print!("Press any key within {timeout} ms to edit the boot arguments");
+	let ticks =
+		u64::try_from(u128::from(timeout) * u128::from(frequency) / 1000).unwrap_or(u64::MAX);
+	let deadline = arch::timestamp().saturating_add(ticks);
+	let key = loop {
+		if let Some(byte) = read_byte() {
+			break Some(byte);
+		}
+		if arch::timestamp() >= deadline {
+			break None;
+		}
+		hint::spin_loop();
+	};
+	println!();
+	if key.is_none() {
+		return cmdline;
+	}
+
+	let Some(line) = read_line(cmdline.unwrap_or_default()) else {
+		info!("Keeping the original command line");
+		return cmdline;
+	};
+
+	let line = String::leak(line).trim();
+	info!("Using the edited command line: {line}");
+	cmdline::set_edited(line);
+	Some(line)
+}
+
+/// Returns the timeout of the prompt in milliseconds.
+fn timeout(cmdline: Option<&str>) -> u64 {
+	let default = DEFAULT_TIMEOUT;
+	match cmdline.and_then(|cmdline| cmdline::values(cmdline, "loader.prompt").last()) {
+		Some(value) => value.parse().unwrap_or_else(|err| {
+			warn!("Ignoring invalid loader.prompt: {err}");
+			default
+		}),
+		None => default,
+	}
+}
+
+fn timestamp_frequency() -> Option<u64> {
+	// The TSC frequency is usually determined when describing the platform, which is too late for the prompt.
+	#[cfg(target_arch = "x86_64")]
+	let frequency = arch::early_timestamp_frequency();
+	#[cfg(not(target_arch = "x86_64"))]
+	let frequency = arch::timestamp_frequency();
+	frequency
+}
+
+fn read_byte() -> Option<u8> {
+	os::CONSOLE.lock().read_byte()
+}
+
+/// Edits `line` and returns it, or `None` if editing was cancelled.
+fn read_line(line: &str) -> Option<String> {
+	let mut line = String::from(line);
+	print!("> {line}");
+
+	let mut escape = false;
+	loop {
+		let Some(byte) = read_byte() else {
+			hint::spin_loop();
+			continue;
+		};
+
+		// Skip the escape sequences of special keys, e.g., `ESC [ D` for the left arrow.
+		if escape {
+			escape = byte == b'[' || (0x30..=0x3f).contains(&byte);
+			continue;
+		}
+
+		match byte {
+			b'\r' | b'\n' => break,
+			CTRL_C => {
+				println!();
+				return None;
+			}
+			BACKSPACE | DELETE if !line.is_empty() => {
+				line.pop();
+				print!("\x08 \x08");
+			}
+			CTRL_U => {
+				for _ in 0..line.len() {
+					print!("\x08 \x08");
+				}
+				line.clear();
+			}
+			ESCAPE => escape = true,
+			0x20..=0x7e => {
+				line.push(char::from(byte));
+				print!("{}", char::from(byte));
+			}
+			_ => {}
+		}
+	}
+
+	println!();
+	Some(line)
+}
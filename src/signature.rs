@@ -0,0 +1,127 @@
+//! Verification of Ed25519 signatures of the application image.
+//!
+//! A signed image is followed by a trailer, which consists of the Ed25519 signature of the image and [`MAGIC`].
+//! A detached signature consists of the trailer alone.
+//! `cargo xtask sign` creates both.
+//!
+//! The image is verified if the loader has a public key.
+//! The public key is compiled into the loader from `LOADER_PUBLIC_KEY` in hexadecimal, or enrolled through a UEFI variable.
+
+use core::fmt;
+
+use ed25519_compact::{PublicKey, Signature};
+use log::info;
+
+/// The magic at the end of the trailer.
+const MAGIC: &[u8; 8] = b"HERMSIG1";
+
+/// The size of a public key.
+pub const PUBLIC_KEY_SIZE: usize = PublicKey::BYTES;
+
+/// The size of the trailer.
+pub const TRAILER_SIZE: usize = Signature::BYTES + MAGIC.len();
+
+/// The public key from `LOADER_PUBLIC_KEY`.
+const PUBLIC_KEY: Option<[u8; PUBLIC_KEY_SIZE]> = match option_env!("LOADER_PUBLIC_KEY") {
+	Some(hex) => Some(decode_hex(hex)),
+	None => None,
+};
+
+const fn decode_hex(hex: &str) -> [u8; PUBLIC_KEY_SIZE] {
+	const fn digit(byte: u8) -> u8 {
+		match byte {
+			b'0'..=b'9' => byte - b'0',
+			b'a'..=b'f' => byte - b'a' + 10,
+			b'A'..=b'F' => byte - b'A' + 10,
+			_ => panic!("LOADER_PUBLIC_KEY must only contain hexadecimal digits"),
+		}
+	}
+
+	let hex = hex.as_bytes();
+	assert!(
+		hex.len() == 2 * PUBLIC_KEY_SIZE,
+		"LOADER_PUBLIC_KEY must be 64 hexadecimal digits"
+	);
+
+	let mut key = [0; PUBLIC_KEY_SIZE];
+	let mut i = 0;
+	while i < key.len() {
+		key[i] = digit(hex[2 * i]) << 4 | digit(hex[2 * i + 1]);
+		i += 1;
+	}
+	key
+}
+
+/// Returns whether `bytes` ends with a signature trailer.
+pub fn has_trailer(bytes: &[u8]) -> bool {
+	bytes.len() >= TRAILER_SIZE && bytes.ends_with(MAGIC)
+}
+
+/// Returns the image without the trailer and the signature from the trailer.
+fn split(image: &[u8]) -> (&[u8], Option<&[u8]>) {
+	if !has_trailer(image) {
+		return (image, None);
+	}
+
+	let (image, trailer) = image.split_at(image.len() - TRAILER_SIZE);
+	(image, Some(&trailer[..Signature::BYTES]))
+}
+
+/// Why the application image was rejected.
+#[derive(Debug)]
+pub enum SignatureError {
+	/// The image is neither signed nor accompanied by a detached signature.
+	Missing,
+	/// The detached signature is not a valid trailer.
+	InvalidDetached,
+	/// The signature does not match any public key.
+	Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Missing => f.write_str("the application image is not signed"),
+			Self::InvalidDetached => f.write_str("the detached signature is malformed"),
+			Self::Mismatch => {
+				f.write_str("the signature of the application image does not match any trusted key")
+			}
+		}
+	}
+}
+
+/// Verifies the signature of `image` and returns the image without the trailer.
+///
+/// The signature is taken from the `detached` signature if present, otherwise from the trailer of `image`.
+/// The image is accepted if it was signed with the compiled-in public key or the `enrolled` public key.
+/// Without any public key, the image is not verified.
+pub fn verify<'a>(
+	image: &'a [u8],
+	detached: Option<&[u8]>,
+	enrolled: Option<[u8; PUBLIC_KEY_SIZE]>,
+) -> Result<&'a [u8], SignatureError> {
+	let (image, signature) = split(image);
+
+	let mut keys = PUBLIC_KEY.into_iter().chain(enrolled).peekable();
+	if keys.peek().is_none() {
+		info!("No public key, skipping signature verification");
+		return Ok(image);
+	}
+
+	let signature = match detached {
+		Some(detached) if detached.len() != TRAILER_SIZE || !has_trailer(detached) => {
+			return Err(SignatureError::InvalidDetached);
+		}
+		Some(detached) => &detached[..Signature::BYTES],
+		None => signature.ok_or(SignatureError::Missing)?,
+	};
+	let signature = Signature::from_slice(signature).unwrap();
+
+	let verified = keys.any(|key| PublicKey::new(key).verify(image, &signature).is_ok());
+	if !verified {
+		return Err(SignatureError::Mismatch);
+	}
+
+	info!("Verified the signature of the application image");
+	Ok(image)
+}
@@ -0,0 +1,93 @@
+//! A/B application slots.
+//!
+//! An application may be installed in two slots, so that an upgrade can be written to the inactive slot.
+//! The booted slot is passed to the kernel in `/chosen/hermit,boot-slot`.
+//! On UEFI, the loader counts boot attempts and falls back to the other slot (see `os::uefi::slots`).
+
+use core::fmt;
+
+use one_shot_mutex::OneShotMutex;
+
+use crate::cmdline;
+
+/// An application slot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Slot {
+	A,
+	B,
+}
+
+impl Slot {
+	/// Returns the slot with `index`, which is `0` for [`Slot::A`] and `1` for [`Slot::B`].
+	#[cfg_attr(not(target_os = "uefi"), expect(dead_code))]
+	pub fn from_index(index: u8) -> Option<Self> {
+		match index {
+			0 => Some(Self::A),
+			1 => Some(Self::B),
+			_ => None,
+		}
+	}
+
+	/// Returns the index of the slot.
+	#[cfg_attr(feature = "fc", expect(dead_code))]
+	pub fn index(self) -> u8 {
+		self as u8
+	}
+
+	/// Returns the other slot.
+	#[cfg_attr(not(target_os = "uefi"), expect(dead_code))]
+	pub fn other(self) -> Self {
+		match self {
+			Self::A => Self::B,
+			Self::B => Self::A,
+		}
+	}
+
+	/// Returns the name of the slot, which is also the file extension of the slot on UEFI.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::A => "a",
+			Self::B => "b",
+		}
+	}
+}
+
+impl fmt::Display for Slot {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+/// Returns the slot from `loader.slot=<a|b>`.
+#[cfg_attr(feature = "fc", expect(dead_code))]
+pub fn from_cmdline(cmdline: &str) -> Option<Slot> {
+	match cmdline::values(cmdline, "loader.slot").last()? {
+		"a" => Some(Slot::A),
+		"b" => Some(Slot::B),
+		value => {
+			log::warn!("Ignoring unknown loader.slot={value}");
+			None
+		}
+	}
+}
+
+/// The booted slot.
+#[derive(Clone, Copy)]
+pub struct Booted {
+	pub slot: Slot,
+	/// The UEFI variable with the boot state as in efivarfs, i.e., `<NAME>-<VENDOR GUID>`.
+	pub state_variable: Option<&'static str>,
+}
+
+static BOOTED: OneShotMutex<Option<Booted>> = OneShotMutex::new(None);
+
+/// Records the booted slot.
+#[cfg_attr(feature = "fc", expect(dead_code))]
+pub fn set_booted(booted: Booted) {
+	*BOOTED.lock() = Some(booted);
+}
+
+/// Returns the booted slot if the application has slots.
+pub fn booted() -> Option<Booted> {
+	*BOOTED.lock()
+}
@@ -13,6 +13,10 @@ use crate::target::Target;
 pub struct Build {
 	#[command(flatten)]
 	pub cargo_build: CargoBuild,
+
+	/// The action after fatal errors, which is passed as `LOADER_FATAL`.
+	#[arg(skip)]
+	pub loader_fatal: Option<&'static str>,
 }
 
 impl Build {
@@ -21,14 +25,19 @@ impl Build {
 
 		let sh = crate::sh()?;
 
+		let embed_symbols = self.cargo_build.artifact.target != Target::X86_64Uefi;
+		let build_object = self.cargo_build.artifact.build_object();
+
 		eprintln!("Building loader");
-		cmd!(sh, "cargo build")
-			.env("CARGO_ENCODED_RUSTFLAGS", self.cargo_encoded_rustflags()?)
-			.args(self.cargo_build.artifact.target.cargo_args())
-			.cargo_build_args(&self.cargo_build)
-			.run()?;
+		self.cargo_build(None)?;
+
+		// The symbol table is placed behind the code, so its size does not change the addresses of the functions.
+		if embed_symbols {
+			let symbols_size = build_object.symbol_table_size()?;
+			eprintln!("Building loader with space for {symbols_size} B of symbols");
+			self.cargo_build(Some(symbols_size))?;
+		}
 
-		let build_object = self.cargo_build.artifact.build_object();
 		let dist_object = self.cargo_build.artifact.dist_object();
 		eprintln!(
 			"Copying {} to {}",
@@ -38,6 +47,11 @@ impl Build {
 		sh.create_dir(dist_object.as_ref().parent().unwrap())?;
 		sh.copy_file(&build_object, &dist_object)?;
 
+		if embed_symbols {
+			eprintln!("Embedding symbols");
+			dist_object.embed_symbols()?;
+		}
+
 		if self.cargo_build.artifact.target == Target::X86_64 {
 			eprintln!("Converting object to elf32-i386");
 			dist_object.convert_to_elf32_i386()?;
@@ -47,6 +61,24 @@ impl Build {
 		Ok(())
 	}
 
+	/// Builds the loader, reserving `symbols_size` bytes for the symbol table.
+	fn cargo_build(&self, symbols_size: Option<usize>) -> Result<()> {
+		let sh = crate::sh()?;
+		let mut cmd = cmd!(sh, "cargo build")
+			.env("CARGO_ENCODED_RUSTFLAGS", self.cargo_encoded_rustflags()?)
+			.args(self.cargo_build.artifact.target.cargo_args())
+			.cargo_build_args(&self.cargo_build);
+		cmd = match symbols_size {
+			Some(symbols_size) => cmd.env("LOADER_SYMBOLS_SIZE", symbols_size.to_string()),
+			None => cmd.env_remove("LOADER_SYMBOLS_SIZE"),
+		};
+		if let Some(loader_fatal) = self.loader_fatal {
+			cmd = cmd.env("LOADER_FATAL", loader_fatal);
+		}
+		cmd.run()?;
+		Ok(())
+	}
+
 	fn cargo_encoded_rustflags(&self) -> Result<String> {
 		let outer_rustflags = match env::var("CARGO_ENCODED_RUSTFLAGS") {
 			Ok(s) => Some(s),
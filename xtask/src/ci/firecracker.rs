@@ -25,6 +25,8 @@ impl Firecracker {
 	pub fn run(mut self) -> Result<()> {
 		self.build.cargo_build.features.push("fc".to_string());
 
+		// Firecracker stops when the guest resets.
+		self.build.loader_fatal = Some("reboot");
 		self.build.run()?;
 
 		let sh = crate::sh()?;
@@ -3,7 +3,6 @@ use std::process::{Command, ExitStatus};
 
 use anyhow::{ensure, Result};
 use clap::Args;
-use sysinfo::{CpuRefreshKind, System};
 use xshell::cmd;
 
 use crate::build::Build;
@@ -45,6 +44,8 @@ impl Qemu {
 			eprintln!("::group::cargo build")
 		}
 
+		// Fatal errors exit QEMU with a failure status, since we pass `isa-debug-exit` and `-semihosting`.
+		self.build.loader_fatal = Some("debug-exit");
 		self.build.run()?;
 
 		if super::in_ci() {
@@ -84,7 +85,6 @@ impl Qemu {
 
 	fn machine_args(&self) -> Vec<String> {
 		if self.microvm {
-			let frequency = get_frequency();
 			vec![
 				"-M".to_string(),
 				"microvm,x-option-roms=off,pit=off,pic=off,rtc=on,auto-kernel-cmdline=off,acpi=off"
@@ -93,8 +93,6 @@ impl Qemu {
 				"virtio-mmio.force-legacy=on".to_string(),
 				"-nodefaults".to_string(),
 				"-no-user-config".to_string(),
-				"-append".to_string(),
-				format!("-freq {frequency}"),
 			]
 		} else if self.build.target() == Target::Aarch64 {
 			vec!["-machine".to_string(), "virt,gic-version=3".to_string()]
@@ -238,16 +236,6 @@ impl Qemu {
 	}
 }
 
-fn get_frequency() -> u64 {
-	let mut sys = System::new();
-	sys.refresh_cpu_specifics(CpuRefreshKind::nothing().with_frequency());
-	let frequency = sys.cpus().first().unwrap().frequency();
-	if !sys.cpus().iter().all(|cpu| cpu.frequency() == frequency) {
-		eprintln!("CPU frequencies are not all equal");
-	}
-	frequency
-}
-
 trait ExitStatusExt {
 	fn qemu_success(&self) -> bool;
 }
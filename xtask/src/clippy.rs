@@ -25,6 +25,9 @@ impl Clippy {
 			cmd!(sh, "cargo clippy --target={triple} {feature_flags...}").run()?;
 		}
 
+		// The host tests of the target-independent modules.
+		cmd!(sh, "cargo clippy --tests").run()?;
+
 		cmd!(sh, "cargo clippy --package xtask").run()?;
 
 		Ok(())
@@ -6,6 +6,7 @@ mod cargo_build;
 mod ci;
 mod clippy;
 mod object;
+mod sign;
 mod target;
 
 use std::env;
@@ -20,6 +21,7 @@ enum Cli {
 	#[command(subcommand)]
 	Ci(ci::Ci),
 	Clippy(clippy::Clippy),
+	Sign(sign::Sign),
 }
 
 impl Cli {
@@ -28,6 +30,7 @@ impl Cli {
 			Self::Build(build) => build.run(),
 			Self::Ci(ci) => ci.run(),
 			Self::Clippy(clippy) => clippy.run(),
+			Self::Sign(sign) => sign.run(),
 		}
 	}
 }
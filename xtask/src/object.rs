@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
 use xshell::cmd;
 
 pub struct Object(PathBuf);
@@ -31,4 +31,166 @@ impl Object {
 		cmd!(sh, "{objcopy} --output-target elf32-i386 {object}").run()?;
 		Ok(())
 	}
+
+	/// Returns the size of the symbol table for the `.loader_symbols` section.
+	pub fn symbol_table_size(&self) -> Result<usize> {
+		let (table, _) = self.symbol_table()?;
+		Ok(table.len())
+	}
+
+	/// Embeds the function symbols into the `.loader_symbols` section for backtraces.
+	///
+	/// See `src/backtrace.rs` for the format.
+	pub fn embed_symbols(&self) -> Result<()> {
+		let sh = crate::sh()?;
+		let objcopy = crate::binutil("objcopy")?;
+		let object = self.as_ref();
+
+		let (mut table, capacity) = self.symbol_table()?;
+		ensure!(
+			table.len() <= capacity,
+			"symbol table ({} B) does not fit into .loader_symbols ({capacity} B)",
+			table.len()
+		);
+		table.resize(capacity, 0);
+
+		let table_path = object.with_extension("symbols");
+		sh.write_file(&table_path, table)?;
+		let section = format!(".loader_symbols={}", table_path.display());
+		cmd!(sh, "{objcopy} --update-section {section} {object}").run()?;
+		sh.remove_path(&table_path)?;
+
+		Ok(())
+	}
+
+	/// Returns the symbol table of the function symbols and the size of the `.loader_symbols` section.
+	fn symbol_table(&self) -> Result<(Vec<u8>, usize)> {
+		let sh = crate::sh()?;
+		let nm = crate::binutil("nm")?;
+		let object = self.as_ref();
+
+		let output = cmd!(
+			sh,
+			"{nm} --defined-only --demangle --print-size --numeric-sort {object}"
+		)
+		.read()?;
+
+		let mut start = None;
+		let mut end = None;
+		let mut symbols = Vec::<(u64, u32, String)>::new();
+		for line in output.lines() {
+			let mut fields = line.splitn(4, ' ');
+			let (Some(addr), Some(size), Some(ty), Some(name)) =
+				(fields.next(), fields.next(), fields.next(), fields.next())
+			else {
+				continue;
+			};
+			let addr = u64::from_str_radix(addr, 16)?;
+
+			match name {
+				"loader_symbols_start" => start = Some(addr),
+				"loader_symbols_end" => end = Some(addr),
+				_ => {}
+			}
+
+			if !matches!(ty, "t" | "T" | "W") || name.starts_with(".L") {
+				continue;
+			}
+			if symbols.last().is_some_and(|(last, _, _)| *last == addr) {
+				continue;
+			}
+
+			let size = u32::from_str_radix(size, 16).unwrap_or(0);
+			symbols.push((addr, size, demangle(name)));
+		}
+
+		let start = start.context("could not find loader_symbols_start")?;
+		let end = end.context("could not find loader_symbols_end")?;
+		let capacity = usize::try_from(end - start)?;
+
+		let count = u32::try_from(symbols.len())?;
+		let mut names = Vec::new();
+		let mut table = Vec::new();
+		table.extend_from_slice(b"HLSYMTAB");
+		table.extend_from_slice(&count.to_le_bytes());
+		table.extend_from_slice(&0u32.to_le_bytes());
+		let names_start = table.len() + symbols.len() * 16;
+		for (addr, size, name) in &symbols {
+			let name_offset = u32::try_from(names_start + names.len())?;
+			names.extend_from_slice(name.as_bytes());
+			names.push(0);
+
+			table.extend_from_slice(&addr.to_le_bytes());
+			table.extend_from_slice(&size.to_le_bytes());
+			table.extend_from_slice(&name_offset.to_le_bytes());
+		}
+		table.extend_from_slice(&names);
+
+		Ok((table, capacity))
+	}
+}
+
+/// Demangles legacy-mangled Rust symbols, which `llvm-nm --demangle` only demangles as C++ symbols.
+fn demangle(name: &str) -> String {
+	let mut demangled = String::with_capacity(name.len());
+	for (i, element) in strip_hash(name).split("::").enumerate() {
+		if i > 0 {
+			demangled.push_str("::");
+		}
+
+		// Elements starting with an escape are prefixed with `_`.
+		let mut rest = match element.strip_prefix('_') {
+			Some(escaped) if escaped.starts_with('$') => escaped,
+			_ => element,
+		};
+		while let Some(c) = rest.chars().next() {
+			if let Some(after) = rest.strip_prefix("..") {
+				demangled.push_str("::");
+				rest = after;
+				continue;
+			}
+
+			let escape = rest
+				.strip_prefix('$')
+				.and_then(|escape| escape.split_once('$'))
+				.and_then(|(escape, after)| Some((unescape(escape)?, after)));
+			if let Some((unescaped, after)) = escape {
+				demangled.push(unescaped);
+				rest = after;
+			} else {
+				demangled.push(c);
+				rest = &rest[c.len_utf8()..];
+			}
+		}
+	}
+	demangled
+}
+
+fn unescape(escape: &str) -> Option<char> {
+	match escape {
+		"SP" => Some('@'),
+		"BP" => Some('*'),
+		"RF" => Some('&'),
+		"LT" => Some('<'),
+		"GT" => Some('>'),
+		"LP" => Some('('),
+		"RP" => Some(')'),
+		"C" => Some(','),
+		_ => {
+			let code = u32::from_str_radix(escape.strip_prefix('u')?, 16).ok()?;
+			char::from_u32(code)
+		}
+	}
+}
+
+/// Removes the hash suffix from legacy-mangled Rust symbols.
+fn strip_hash(name: &str) -> &str {
+	// LLVM appends `.llvm.<hash>` to local symbols that are promoted to global ones.
+	let name = name.split_once(" (.llvm.").map_or(name, |(name, _)| name);
+	match name.rsplit_once("::h") {
+		Some((name, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+			name
+		}
+		_ => name,
+	}
 }
@@ -0,0 +1,110 @@
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use anyhow::{ensure, Context, Result};
+use clap::Args;
+use ed25519_compact::{KeyPair, Seed};
+
+/// The magic at the end of the signature trailer.
+///
+/// See `src/signature.rs` for the format.
+const MAGIC: &[u8; 8] = b"HERMSIG1";
+
+/// Sign an application image.
+#[derive(Args)]
+pub struct Sign {
+	/// File with the secret key as hexadecimal seed.
+	#[arg(long)]
+	key: PathBuf,
+
+	/// Generate a new secret key and write it to the key file.
+	#[arg(long)]
+	new_key: bool,
+
+	/// Write a detached signature instead of appending the signature to the image.
+	#[arg(long)]
+	detached: bool,
+
+	/// Output file [default: `<IMAGE>.signed`, or `<IMAGE>.sig` with `--detached`].
+	#[arg(short, long)]
+	output: Option<PathBuf>,
+
+	/// Application image.
+	image: PathBuf,
+}
+
+impl Sign {
+	pub fn run(self) -> Result<()> {
+		let sh = crate::sh()?;
+
+		let key_pair = if self.new_key {
+			ensure!(
+				!sh.path_exists(&self.key),
+				"{} exists already",
+				self.key.display()
+			);
+			let key_pair = KeyPair::from_seed(Seed::generate());
+			sh.write_file(&self.key, format!("{}\n", hex(key_pair.sk.seed().as_ref())))?;
+			eprintln!("Wrote new secret key to {}", self.key.display());
+			key_pair
+		} else {
+			let seed = sh.read_file(&self.key)?;
+			let seed =
+				unhex(seed.trim()).context("the key file must contain a hexadecimal seed")?;
+			let seed = Seed::from_slice(&seed).context("the key file must contain 32 bytes")?;
+			KeyPair::from_seed(seed)
+		};
+
+		let mut image = sh.read_binary_file(&self.image)?;
+		if image.len() >= 64 + MAGIC.len() && image.ends_with(MAGIC) {
+			eprintln!("Replacing the existing signature");
+			image.truncate(image.len() - 64 - MAGIC.len());
+		}
+
+		let signature = key_pair.sk.sign(&image, None);
+		let mut trailer = signature.to_vec();
+		trailer.extend_from_slice(MAGIC);
+
+		let extension = if self.detached { "sig" } else { "signed" };
+		let output = self.output.unwrap_or_else(|| {
+			let mut output = OsString::from(&self.image);
+			output.push(".");
+			output.push(extension);
+			output.into()
+		});
+
+		if self.detached {
+			sh.write_file(&output, trailer)?;
+		} else {
+			image.extend_from_slice(&trailer);
+			sh.write_file(&output, image)?;
+		}
+
+		eprintln!("Signed image available at {}", output.display());
+		eprintln!(
+			"Build the loader with LOADER_PUBLIC_KEY={} to verify it",
+			hex(key_pair.pk.as_ref())
+		);
+
+		Ok(())
+	}
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().fold(String::new(), |mut s, byte| {
+		write!(s, "{byte:02x}").unwrap();
+		s
+	})
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+	if !hex.len().is_multiple_of(2) {
+		return None;
+	}
+
+	(0..hex.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+		.collect()
+}
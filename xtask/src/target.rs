@@ -61,14 +61,22 @@ impl Target {
 			Self::X86_64 => &[
 				"-Clink-arg=-Tsrc/arch/x86_64/link.ld",
 				"-Crelocation-model=static",
+				"-Cforce-frame-pointers=yes",
 			],
 			Self::X86_64Fc => &[
 				"-Clink-arg=-Tsrc/arch/x86_64/link_fc.ld",
 				"-Crelocation-model=static",
+				"-Cforce-frame-pointers=yes",
 			],
 			Self::X86_64Uefi => &[],
-			Self::Aarch64 => &["-Clink-arg=-Tsrc/arch/aarch64/link.ld"],
-			Self::Riscv64 => &["-Clink-arg=-Tsrc/arch/riscv64/link.ld"],
+			Self::Aarch64 => &[
+				"-Clink-arg=-Tsrc/arch/aarch64/link.ld",
+				"-Cforce-frame-pointers=yes",
+			],
+			Self::Riscv64 => &[
+				"-Clink-arg=-Tsrc/arch/riscv64/link.ld",
+				"-Cforce-frame-pointers=yes",
+			],
 		}
 	}
 